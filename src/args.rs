@@ -0,0 +1,109 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// The errors that can occur while parsing the command line arguments
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    /// An argument that needs a value is the last one, or is followed by another argument
+    MissingValue { arg: String },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::MissingValue { arg } => write!(f, "{} needs a value", arg),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+/// The command line arguments
+/// # Remarks
+/// The arguments follow the vanilla conventions: `-iwad <file>` selects the IWAD and `-file`
+/// takes every following argument up to the next one starting with `-` as a PWAD. `-pcspeaker`
+/// plays the PC speaker sound effects instead of the digitized ones. Unknown arguments are
+/// ignored.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// The IWAD given with `-iwad`
+    pub iwad: Option<PathBuf>,
+    /// The PWADs given with `-file`, in load order
+    pub files: Vec<PathBuf>,
+    /// Whether `-pcspeaker` was given
+    pub pc_speaker: bool,
+}
+
+impl Args {
+    /// Parses the command line arguments, without the name of the executable
+    /// # Returns
+    /// The arguments, or a `MissingValue` error if `-iwad` or `-file` has no value
+    /// # Examples
+    /// ```
+    /// use doom::args::Args;
+    /// let args = Args::parse(["-iwad", "doom2.wad", "-file", "a.wad", "b.wad"].map(String::from));
+    /// assert_eq!(args.unwrap().files.len(), 2);
+    /// ```
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut result = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            // Values never start with `-`, so a missing value isn't mistaken for the next argument
+            let mut values = std::iter::from_fn(|| args.next_if(|next| !next.starts_with('-')));
+            let missing = || ArgsError::MissingValue { arg: arg.clone() };
+            match arg.to_ascii_lowercase().as_str() {
+                "-iwad" => result.iwad = Some(PathBuf::from(values.next().ok_or_else(missing)?)),
+                "-file" => {
+                    let count = result.files.len();
+                    result.files.extend(values.map(PathBuf::from));
+                    if result.files.len() == count {
+                        return Err(missing());
+                    }
+                }
+                "-pcspeaker" => result.pc_speaker = true,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_iwad_and_files() {
+        let args = parse(&[
+            "-IWAD",
+            "doom2.wad",
+            "-file",
+            "a.wad",
+            "b.wad",
+            "-pcspeaker",
+        ])
+        .unwrap();
+        assert_eq!(args.iwad, Some(PathBuf::from("doom2.wad")));
+        assert_eq!(
+            args.files,
+            vec![PathBuf::from("a.wad"), PathBuf::from("b.wad")]
+        );
+        assert!(args.pc_speaker);
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        for args in [&["-iwad"][..], &["-iwad", "-file", "a.wad"], &["-file"]] {
+            assert_eq!(
+                parse(args).err(),
+                Some(ArgsError::MissingValue {
+                    arg: args[0].to_string()
+                })
+            );
+        }
+    }
+}
@@ -1,61 +1,549 @@
-use rodio::Source;
-use std::io::Cursor;
-use std::io::Read;
+#[cfg(feature = "rodio")]
+use crate::audio_backend::RodioBackend;
+use crate::audio_backend::{AudioBackend, NullBackend};
+use crate::genmidi::GenMidi;
+use crate::level::Thing;
+use crate::mixer::{Mixer, Origin, MAX_CHANNEL_VOLUME, NORM_SEPARATION, NUM_CHANNELS};
+use crate::music::Mus;
+use crate::opl_music::MusicPlayer;
+use crate::wad::{LumpReader, WadError};
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
-struct DmxDecoder {
-    data: Cursor<Vec<u8>>,
-    sample_rate: u32,
+/// The format number of digitized sound lumps
+const DMX_FORMAT: u16 = 3;
+/// The DMX library pads the samples with 16 bytes on each side, these are not part of the sound
+const DMX_PADDING: usize = 16;
+
+/// A digitized sound effect, e.g. `DSPISTOL`
+pub struct Sound {
+    /// The sample rate in Hz, usually 11025 but some sounds use 22050
+    pub sample_rate: u32,
+    /// Unsigned 8-bit mono samples, without the padding
+    pub samples: Vec<u8>,
 }
 
-impl DmxDecoder {
-    pub fn new(data: Vec<u8>, sample_rate: u32) -> Self {
-        Self {
-            data: Cursor::new(data),
+impl Sound {
+    /// Decodes a DMX sound lump
+    /// # Arguments
+    /// * `name` - The name of the lump, used to report errors
+    /// * `data` - The lump data
+    /// # Returns
+    /// The sound, an `UnsupportedFormat` error if the lump isn't a digitized sound, or a
+    /// `TruncatedLump` or `BadHeader` error if the sample count doesn't fit the lump
+    /// # Remarks
+    /// The lump starts with the format number (3), the sample rate and the sample count, which
+    /// includes the padding on both sides. Like Chocolate Doom, the padding is skipped.
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, WadError> {
+        let mut reader = LumpReader::new(name, data);
+        let format = reader.read_u16()?;
+        if format != DMX_FORMAT {
+            return Err(WadError::UnsupportedFormat {
+                name: name.to_string(),
+                offset: 0,
+                reason: format!("sound format {} is not a digitized sound", format),
+            });
+        }
+        let sample_rate = reader.read_u16()? as u32;
+        let count = reader.read_u32()? as usize;
+        if count < DMX_PADDING * 2 {
+            return Err(WadError::BadHeader {
+                name: name.to_string(),
+                offset: 4,
+                reason: format!("{} samples can't hold the padding", count),
+            });
+        }
+        if sample_rate == 0 {
+            return Err(WadError::BadHeader {
+                name: name.to_string(),
+                offset: 2,
+                reason: "the sample rate is 0".to_string(),
+            });
+        }
+
+        let samples = reader.read_bytes(count)?;
+        Ok(Self {
             sample_rate,
+            samples: samples[DMX_PADDING..count - DMX_PADDING].to_vec(),
+        })
+    }
+
+    /// The length of the sound
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Which sound effects are played
+/// # Remarks
+/// Every sound effect comes in two versions: a digitized one (`DS` lumps) and a PC speaker one
+/// (`DP` lumps). Both play through the same mixer.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum SfxDevice {
+    #[default]
+    Digital,
+    PcSpeaker,
+}
+
+impl SfxDevice {
+    /// Gets the name of the lump of a sound effect for this device
+    /// # Arguments
+    /// * `name` - The name of the sound effect without a prefix, e.g. `PISTOL`
+    /// # Examples
+    /// ```
+    /// use doom::audio::SfxDevice;
+    /// assert_eq!(SfxDevice::PcSpeaker.lump_name("PISTOL"), "DPPISTOL");
+    /// ```
+    pub fn lump_name(&self, name: &str) -> String {
+        match self {
+            SfxDevice::Digital => format!("DS{}", name),
+            SfxDevice::PcSpeaker => format!("DP{}", name),
         }
     }
 }
 
-impl Iterator for DmxDecoder {
-    type Item = i16;
+/// Sounds further away than this, in map units, are not heard
+pub const CLIPPING_DIST: f64 = 1200.0;
+/// Sounds closer than this, in map units, are heard at full volume
+pub const CLOSE_DIST: f64 = 200.0;
+/// The distance over which sounds fade out
+const ATTENUATOR: f64 = CLIPPING_DIST - CLOSE_DIST;
+/// How far a sound to the side moves the stereo separation away from `NORM_SEPARATION`
+const STEREO_SWING: f64 = 96.0;
+/// The lowest volume of a sound on a boss map, where sounds are heard from any distance
+const BOSS_MAP_MIN_VOLUME: f64 = 15.0;
+
+/// The sample rate the sounds are mixed at
+const OUTPUT_RATE: u32 = 44100;
+
+/// Where sounds are heard from, usually the player
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Listener {
+    /// The position in map units
+    pub x: f64,
+    pub y: f64,
+    /// The angle the listener faces in radians; 0 is east and angles go counterclockwise
+    pub angle: f64,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut buf = [0; 1];
-        match self.data.read(&mut buf) {
-            Ok(0) => None,                              // We've reached end of data
-            Ok(_) => Some((buf[0] as i16 - 128) * 256), // convert u8 to i16
-            Err(_) => None, // Handle read error, here we just stop the iterator
+impl Listener {
+    /// Creates a listener at a map thing, like the player start
+    pub fn from_thing(thing: &Thing) -> Self {
+        Self {
+            x: thing.x as f64,
+            y: thing.y as f64,
+            angle: (thing.angle as f64).to_radians(),
         }
     }
 }
 
-impl Source for DmxDecoder {
-    fn current_frame_len(&self) -> Option<usize> {
-        None // We don't know the current frame length; it's infinite until the data runs out
+/// Calculates the volume and stereo separation of a sound, like vanilla's S_AdjustSoundParams
+/// # Arguments
+/// * `listener` - Where the sound is heard from
+/// * `x` - The x position of the source in map units
+/// * `y` - The y position of the source in map units
+/// * `volume` - The volume of the sound up close, 0 to `MAX_CHANNEL_VOLUME`
+/// * `boss_map` - Whether this is map 8, where vanilla keeps sounds audible at any distance so
+///   the death of the boss is always heard
+/// # Returns
+/// The channel volume and stereo separation, or `None` if the sound is too far away to be heard
+/// # Remarks
+/// The distance is vanilla's approximation, the longest axis plus half the shortest, so the
+/// sounds fade out over the same distances as in the original.
+pub fn adjust_sound_params(
+    listener: &Listener,
+    x: f64,
+    y: f64,
+    volume: i32,
+    boss_map: bool,
+) -> Option<(i32, i32)> {
+    let (dx, dy) = ((x - listener.x).abs(), (y - listener.y).abs());
+    let distance = dx + dy - dx.min(dy) / 2.0;
+    if !boss_map && distance > CLIPPING_DIST {
+        return None;
+    }
+
+    // Sounds to the left of the listener are louder on the left
+    let angle = (y - listener.y).atan2(x - listener.x) - listener.angle;
+    let separation = NORM_SEPARATION - (STEREO_SWING * angle.sin()) as i32;
+
+    let base = volume as f64;
+    let volume = if distance < CLOSE_DIST {
+        base
+    } else if boss_map {
+        let distance = distance.min(CLIPPING_DIST);
+        BOSS_MAP_MIN_VOLUME + (base - BOSS_MAP_MIN_VOLUME) * (CLIPPING_DIST - distance) / ATTENUATOR
+    } else {
+        base * (CLIPPING_DIST - distance) / ATTENUATOR
+    } as i32;
+
+    match volume > 0 {
+        true => Some((volume, separation)),
+        false => None,
+    }
+}
+
+/// Plays sound effects and music through an audio backend
+/// # Remarks
+/// The channels and the music are mixed by a shared `Mixer`, which the backend takes the sound
+/// from. `update` must be called every frame with the time that passed, so backends that aren't
+/// driven by an audio device keep up.
+/// # Examples
+/// ```no_run
+/// use doom::audio::SoundSystem;
+/// use doom::wad::WadFile;
+/// let wad = WadFile::load("doom1.wad").unwrap();
+/// let sound_system = SoundSystem::new();
+/// sound_system.start_sound(wad.get_sound("DSPISTOL").unwrap(), 64, None, 127);
+/// ```
+pub struct SoundSystem {
+    mixer: Arc<Mutex<Mixer>>,
+    backend: RefCell<Box<dyn AudioBackend>>,
+    /// Whether the current map is map 8, see `adjust_sound_params`
+    boss_map: Cell<bool>,
+}
+
+impl SoundSystem {
+    /// Creates a sound system that plays on the default audio device
+    /// # Remarks
+    /// When there is no audio device, or the `rodio` feature is off, the sound is mixed by a
+    /// `NullBackend` instead: sounds are still assigned to channels but nothing is heard.
+    pub fn new() -> Self {
+        #[cfg(feature = "rodio")]
+        match Self::with_backend(Box::<RodioBackend>::default()) {
+            Ok(sound_system) => return sound_system,
+            Err(e) => eprintln!("Unable to play sounds, continuing without sound: {}", e),
+        }
+        Self::with_backend(Box::<NullBackend>::default()).expect("The null backend can't fail")
+    }
+
+    /// Creates a sound system that plays through a backend
+    /// # Returns
+    /// The sound system, or the error of the backend if it couldn't start
+    /// # Examples
+    /// ```
+    /// use doom::audio::SoundSystem;
+    /// use doom::audio_backend::NullBackend;
+    /// let sound_system = SoundSystem::with_backend(Box::new(NullBackend::default())).unwrap();
+    /// ```
+    pub fn with_backend(mut backend: Box<dyn AudioBackend>) -> io::Result<Self> {
+        let mixer = Arc::new(Mutex::new(Mixer::new(OUTPUT_RATE)));
+        backend.start(mixer.clone())?;
+        Ok(Self {
+            mixer,
+            backend: RefCell::new(backend),
+            boss_map: Cell::new(false),
+        })
+    }
+
+    /// Lets the backend mix the sound of the time that passed
+    /// # Arguments
+    /// * `elapsed` - The time since the last update
+    pub fn update(&self, elapsed: Duration) -> io::Result<()> {
+        self.backend.borrow_mut().advance(elapsed)
+    }
+
+    /// Locks the mixer, e.g. to change several channels at once
+    pub fn mixer(&self) -> MutexGuard<'_, Mixer> {
+        self.mixer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts a sound straight ahead, like the sounds the listener makes
+    /// # Arguments
+    /// * `sound` - The sound to play
+    /// * `priority` - The priority of the sound, a lower number is more important
+    /// * `origin` - What makes the sound; a new sound from the same origin replaces the old one
+    /// * `volume` - The volume, 0 to `MAX_CHANNEL_VOLUME`
+    /// # Returns
+    /// The channel the sound plays on, or `None` if all channels hold more important sounds
+    pub fn start_sound(
+        &self,
+        sound: Arc<Sound>,
+        priority: i32,
+        origin: Option<Origin>,
+        volume: i32,
+    ) -> Option<usize> {
+        self.mixer()
+            .start(sound, priority, origin, volume, NORM_SEPARATION)
+    }
+
+    /// Starts a sound made by something on the map, panned and attenuated for the listener
+    /// # Arguments
+    /// * `sound` - The sound to play
+    /// * `priority` - The priority of the sound, a lower number is more important
+    /// * `origin` - What makes the sound
+    /// * `position` - The position of the origin in map units
+    /// * `volume` - The volume up close, 0 to `MAX_CHANNEL_VOLUME`; it is kept on the channel so
+    ///   `update_sounds` attenuates from it
+    /// * `listener` - Where the sound is heard from
+    /// # Returns
+    /// The channel the sound plays on, or `None` if the sound is too far away to be heard or all
+    /// channels hold more important sounds
+    pub fn start_sound_at(
+        &self,
+        sound: Arc<Sound>,
+        priority: i32,
+        origin: Origin,
+        position: (f64, f64),
+        volume: i32,
+        listener: &Listener,
+    ) -> Option<usize> {
+        let volume = volume.clamp(0, MAX_CHANNEL_VOLUME);
+        let (attenuated, separation) = adjust_sound_params(
+            listener,
+            position.0,
+            position.1,
+            volume,
+            self.boss_map.get(),
+        )?;
+        let mut mixer = self.mixer();
+        let channel = mixer.start(sound, priority, Some(origin), volume, separation)?;
+        mixer
+            .channel_mut(channel)
+            .expect("Channel was just started")
+            .volume = attenuated;
+        Some(channel)
+    }
+
+    /// Updates the volume and separation of the playing sounds, like vanilla's S_UpdateSounds
+    /// # Arguments
+    /// * `listener` - Where the sounds are heard from
+    /// * `position_of` - Gets the current position of an origin, or `None` for sounds that
+    ///   shouldn't be positioned, like the ones the listener makes
+    /// # Remarks
+    /// This should be called every tic, so sounds follow the listener and their sources as they
+    /// move. The volume is attenuated from the one the sound was started with, so the master
+    /// volume and linked sound adjustments are kept. Sounds that moved out of range are stopped.
+    pub fn update_sounds(
+        &self,
+        listener: &Listener,
+        position_of: impl Fn(Origin) -> Option<(f64, f64)>,
+    ) {
+        let boss_map = self.boss_map.get();
+        let mut mixer = self.mixer();
+        for index in 0..NUM_CHANNELS {
+            let playing = mixer.channel(index).and_then(|channel| {
+                let position = channel.origin.and_then(&position_of)?;
+                Some((position, channel.base_volume))
+            });
+            let ((x, y), base_volume) = match playing {
+                Some(playing) => playing,
+                None => continue,
+            };
+
+            match adjust_sound_params(listener, x, y, base_volume, boss_map) {
+                Some((volume, separation)) => {
+                    let channel = mixer.channel_mut(index).expect("Channel is playing");
+                    channel.volume = volume;
+                    channel.separation = separation.clamp(0, 254);
+                }
+                None => mixer.stop_channel(index),
+            }
+        }
+    }
+
+    /// Sets whether the current map is map 8, where sounds are heard from any distance
+    pub fn set_boss_map(&self, boss_map: bool) {
+        self.boss_map.set(boss_map);
+    }
+
+    /// Stops the sounds of an origin
+    pub fn stop_sound(&self, origin: Origin) {
+        self.mixer().stop_origin(origin);
+    }
+
+    /// Sets the master sound effect volume, 0 to 15
+    pub fn set_sfx_volume(&self, volume: i32) {
+        self.mixer().set_sfx_volume(volume);
+    }
+
+    pub fn sfx_volume(&self) -> i32 {
+        self.mixer().sfx_volume()
     }
 
-    fn channels(&self) -> u16 {
-        1 // DMX data is mono
+    /// Starts playing a song on the emulated OPL2, replacing the one that was playing
+    /// # Arguments
+    /// * `song` - The song to play
+    /// * `bank` - The instruments, from the GENMIDI lump
+    /// * `looping` - Whether the song starts over when it ends, as level music does
+    pub fn play_music(&self, song: Arc<Mus>, bank: Arc<GenMidi>, looping: bool) {
+        let mut mixer = self.mixer();
+        let player = MusicPlayer::new(song, bank, mixer.output_rate(), looping);
+        mixer.play_music(player);
     }
 
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    pub fn stop_music(&self) {
+        self.mixer().stop_music();
     }
 
-    fn total_duration(&self) -> Option<Duration> {
-        None // We don't know the total duration
+    /// Sets the music volume, 0 to 15
+    pub fn set_music_volume(&self, volume: i32) {
+        self.mixer().set_music_volume(volume);
+    }
+
+    pub fn music_volume(&self) -> i32 {
+        self.mixer().music_volume()
+    }
+}
+
+impl Default for SoundSystem {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-pub fn play_sound(lump: Vec<u8>) {
-    let lump = lump.clone(); // clone the lump to move it into the new thread
-    std::thread::spawn(move || {
-        let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-        let dmx_source = DmxDecoder::new(lump.to_vec(), 11025);
-        stream_handle
-            .play_raw(dmx_source.convert_samples::<f32>())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a DMX sound lump with a sample count and samples
+    fn sound_lump(format: u16, sample_rate: u16, count: u32, samples: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&format.to_le_bytes());
+        data.extend_from_slice(&sample_rate.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(samples);
+        data
+    }
+
+    #[test]
+    fn decode_skips_the_padding() {
+        let samples: Vec<u8> = (0..40).collect();
+        let sound = Sound::decode("DSTEST", &sound_lump(3, 11025, 40, &samples)).unwrap();
+        assert_eq!(sound.sample_rate, 11025);
+        assert_eq!(sound.samples, (16..24).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn decode_rejects_other_formats() {
+        // A PC speaker sound
+        assert!(matches!(
+            Sound::decode("DPTEST", &sound_lump(0, 3, 0, &[])),
+            Err(WadError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_sample_counts_past_the_end_of_the_lump() {
+        assert!(matches!(
+            Sound::decode("DSTEST", &sound_lump(3, 11025, 41, &[128; 40])),
+            Err(WadError::TruncatedLump { .. })
+        ));
+        assert!(matches!(
+            Sound::decode("DSTEST", &sound_lump(3, 11025, 20, &[128; 20])),
+            Err(WadError::BadHeader { offset: 4, .. })
+        ));
+    }
+
+    /// A listener at the origin facing east
+    const LISTENER: Listener = Listener {
+        x: 0.0,
+        y: 0.0,
+        angle: 0.0,
+    };
+
+    // The expected values are the ones vanilla's S_AdjustSoundParams gives at full volume
+
+    #[test]
+    fn close_sounds_play_at_full_volume() {
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 150.0, 0.0, 127, false),
+            Some((127, 128))
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 199.0, 0.0, 127, true),
+            Some((127, 128))
+        );
+    }
+
+    #[test]
+    fn sounds_fade_out_up_to_the_clipping_distance() {
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 700.0, 0.0, 127, false),
+            Some((63, 128))
+        );
+        // The approximate distance is 300 + 400 - 300 / 2 = 550
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 300.0, 400.0, 127, false).map(|(v, _)| v),
+            Some(82)
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 1195.0, 0.0, 127, false),
+            None
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 1201.0, 0.0, 127, false),
+            None
+        );
+    }
+
+    #[test]
+    fn sounds_never_fade_out_on_the_boss_map() {
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 700.0, 0.0, 127, true),
+            Some((71, 128))
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 1200.0, 0.0, 127, true),
+            Some((15, 128))
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 5000.0, 0.0, 127, true),
+            Some((15, 128))
+        );
+    }
+
+    #[test]
+    fn sounds_to_the_side_are_separated() {
+        let separation = |x, y| adjust_sound_params(&LISTENER, x, y, 127, false).map(|(_, s)| s);
+        assert_eq!(separation(0.0, 500.0), Some(32));
+        assert_eq!(separation(0.0, -500.0), Some(224));
+        assert_eq!(separation(-500.0, 0.0), Some(128));
+
+        // Turning the listener to face the sound puts it straight ahead
+        let listener = Listener {
+            angle: std::f64::consts::FRAC_PI_2,
+            ..LISTENER
+        };
+        assert_eq!(
+            adjust_sound_params(&listener, 0.0, 500.0, 127, false),
+            Some((88, 128))
+        );
+    }
+
+    #[test]
+    fn quieter_sounds_fade_out_from_their_own_volume() {
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 150.0, 0.0, 64, false),
+            Some((64, 128))
+        );
+        assert_eq!(
+            adjust_sound_params(&LISTENER, 700.0, 0.0, 64, false),
+            Some((32, 128))
+        );
+    }
+
+    #[test]
+    fn updates_attenuate_from_the_starting_volume() {
+        let sound_system = SoundSystem::with_backend(Box::<NullBackend>::default()).unwrap();
+        let sound = Arc::new(Sound {
+            sample_rate: 11025,
+            samples: vec![128; 11025],
+        });
+        let channel = sound_system
+            .start_sound_at(sound, 64, 1, (700.0, 0.0), 64, &LISTENER)
             .unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    });
+        let volume =
+            |sound_system: &SoundSystem| sound_system.mixer().channel(channel).unwrap().volume;
+        assert_eq!(volume(&sound_system), 32);
+
+        sound_system.update_sounds(&LISTENER, |_| Some((0.0, 0.0)));
+        assert_eq!(volume(&sound_system), 64);
+        sound_system.update_sounds(&LISTENER, |_| Some((700.0, 0.0)));
+        assert_eq!(volume(&sound_system), 32);
+        sound_system.update_sounds(&LISTENER, |_| Some((2000.0, 0.0)));
+        assert!(sound_system.mixer().channel(channel).is_none());
+    }
 }
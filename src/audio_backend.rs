@@ -0,0 +1,322 @@
+use crate::mixer::Mixer;
+#[cfg(feature = "rodio")]
+use rodio::{OutputStream, Source};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The amount of stereo frames mixed at once
+const MIX_FRAMES: usize = 512;
+/// The size of a WAV header up to the samples
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// Where the mixed sound goes
+/// # Remarks
+/// A backend either pulls the sound from the mixer on its own, like an audio device does, or
+/// mixes it when it is told how much time passed. The second kind lets sounds start, end and
+/// free their channels at the same pace without any audio hardware, e.g. on a server or in a
+/// test.
+pub trait AudioBackend {
+    /// Starts the backend
+    /// # Arguments
+    /// * `mixer` - The mixer to take the sound from, shared with the game
+    fn start(&mut self, mixer: Arc<Mutex<Mixer>>) -> io::Result<()>;
+
+    /// Mixes the sound of the time that passed; backends driven by a device do nothing
+    /// # Arguments
+    /// * `elapsed` - The time since the last call
+    fn advance(&mut self, elapsed: Duration) -> io::Result<()>;
+}
+
+/// Locks a mixer, even when another thread panicked while holding it
+fn lock(mixer: &Mutex<Mixer>) -> std::sync::MutexGuard<'_, Mixer> {
+    mixer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Mixes the sound of a stretch of time, in blocks of at most `MIX_FRAMES` frames
+/// # Remarks
+/// The frames that don't fit in a whole sample are carried over to the next call in `pending`.
+fn mix_elapsed(
+    mixer: &Mutex<Mixer>,
+    elapsed: Duration,
+    pending: &mut f64,
+    mut output: impl FnMut(&[f32]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut mixer = lock(mixer);
+    *pending += elapsed.as_secs_f64() * mixer.output_rate() as f64;
+    let mut frames = *pending as usize;
+    *pending -= frames as f64;
+
+    let mut buffer = vec![0.0; MIX_FRAMES.min(frames) * 2];
+    while frames > 0 {
+        let count = frames.min(MIX_FRAMES);
+        mixer.mix(&mut buffer[..count * 2]);
+        output(&buffer[..count * 2])?;
+        frames -= count;
+    }
+    Ok(())
+}
+
+/// The stream rodio plays, mixed from the channels of the shared mixer
+/// # Remarks
+/// The mixer is locked once per block of `MIX_FRAMES` frames rather than for every sample.
+#[cfg(feature = "rodio")]
+struct MixerSource {
+    mixer: Arc<Mutex<Mixer>>,
+    sample_rate: u32,
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+#[cfg(feature = "rodio")]
+impl Iterator for MixerSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.buffer.len() {
+            lock(&self.mixer).mix(&mut self.buffer);
+            self.position = 0;
+        }
+        self.position += 1;
+        Some(self.buffer[self.position - 1])
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Source for MixerSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None // The stream never ends
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Plays the sound on the default audio device
+/// # Remarks
+/// The device pulls the sound from the mixer on the audio thread, so `advance` does nothing. Only
+/// available with the `rodio` feature, which is on by default.
+#[cfg(feature = "rodio")]
+#[derive(Default)]
+pub struct RodioBackend {
+    /// The stream must be kept alive for as long as sounds should be heard
+    stream: Option<OutputStream>,
+}
+
+#[cfg(feature = "rodio")]
+impl AudioBackend for RodioBackend {
+    /// Opens the default audio device
+    /// # Returns
+    /// An error if there is no audio device or it can't play the stream
+    fn start(&mut self, mixer: Arc<Mutex<Mixer>>) -> io::Result<()> {
+        let (stream, handle) = OutputStream::try_default().map_err(io::Error::other)?;
+        let sample_rate = lock(&mixer).output_rate();
+        let source = MixerSource {
+            mixer,
+            sample_rate,
+            buffer: vec![0.0; MIX_FRAMES * 2],
+            position: MIX_FRAMES * 2,
+        };
+        handle.play_raw(source).map_err(io::Error::other)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn advance(&mut self, _elapsed: Duration) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Mixes the sound and throws it away
+/// # Remarks
+/// Used when there is no audio device. Sounds still play for as long as they last, so the
+/// channels behave as if they were heard.
+#[derive(Default)]
+pub struct NullBackend {
+    mixer: Option<Arc<Mutex<Mixer>>>,
+    pending: f64,
+}
+
+impl AudioBackend for NullBackend {
+    fn start(&mut self, mixer: Arc<Mutex<Mixer>>) -> io::Result<()> {
+        self.mixer = Some(mixer);
+        Ok(())
+    }
+
+    fn advance(&mut self, elapsed: Duration) -> io::Result<()> {
+        match self.mixer.as_ref() {
+            Some(mixer) => mix_elapsed(mixer, elapsed, &mut self.pending, |_| Ok(())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records the mixed sound to a 16-bit stereo WAV file
+/// # Remarks
+/// The header is updated after every write, so the file can be read at any time, e.g. to check
+/// in a test which sounds were played.
+/// # Examples
+/// ```
+/// use doom::audio::SoundSystem;
+/// use doom::audio_backend::WavCaptureBackend;
+/// use std::time::Duration;
+/// let path = std::env::temp_dir().join("capture.wav");
+/// let backend = WavCaptureBackend::create(&path).unwrap();
+/// let sound_system = SoundSystem::with_backend(Box::new(backend)).unwrap();
+/// sound_system.update(Duration::from_secs(1)).unwrap();
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub struct WavCaptureBackend {
+    file: BufWriter<File>,
+    mixer: Option<Arc<Mutex<Mixer>>>,
+    pending: f64,
+    /// The amount of bytes of samples written
+    data_size: u32,
+}
+
+impl WavCaptureBackend {
+    /// Creates the WAV file, replacing it if it exists
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            mixer: None,
+            pending: 0.0,
+            data_size: 0,
+        })
+    }
+
+    /// Writes the header, which holds the sizes of the file and the samples
+    fn write_header(&mut self, sample_rate: u32) -> io::Result<()> {
+        let block_align = 2 * 2; // Two channels of two bytes
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(b"RIFF")?;
+        self.file
+            .write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVEfmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?; // Size of the format chunk
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&2u16.to_le_bytes())?; // Stereo
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file
+            .write_all(&(sample_rate * block_align).to_le_bytes())?;
+        self.file.write_all(&(block_align as u16).to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        self.file.write_all(b"data")?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+}
+
+impl AudioBackend for WavCaptureBackend {
+    fn start(&mut self, mixer: Arc<Mutex<Mixer>>) -> io::Result<()> {
+        let sample_rate = lock(&mixer).output_rate();
+        self.mixer = Some(mixer);
+        self.write_header(sample_rate)
+    }
+
+    fn advance(&mut self, elapsed: Duration) -> io::Result<()> {
+        let mixer = match self.mixer.clone() {
+            Some(mixer) => mixer,
+            None => return Ok(()),
+        };
+        let file = &mut self.file;
+        let data_size = &mut self.data_size;
+        mix_elapsed(&mixer, elapsed, &mut self.pending, |samples| {
+            // The sizes in the header are 32-bit, which limits a capture to about 4 GiB
+            *data_size = data_size
+                .checked_add(samples.len() as u32 * 2)
+                .filter(|size| size.checked_add(WAV_HEADER_SIZE - 8).is_some())
+                .ok_or_else(|| io::Error::other("The capture is too long for a WAV file"))?;
+            for &sample in samples {
+                file.write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?;
+            }
+            Ok(())
+        })?;
+        let sample_rate = lock(&mixer).output_rate();
+        self.write_header(sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Sound;
+
+    /// A sample rate that fits whole frames in a millisecond
+    const RATE: u32 = 10000;
+
+    /// A mixer at the sample rate of the sound, with a loud sound of 100 samples on the left
+    fn playing_mixer() -> Arc<Mutex<Mixer>> {
+        let mut mixer = Mixer::new(RATE);
+        let sound = Sound {
+            sample_rate: RATE,
+            samples: vec![255; 100],
+        };
+        mixer.start(Arc::new(sound), 64, Some(1), 127, 0);
+        Arc::new(Mutex::new(mixer))
+    }
+
+    #[test]
+    fn the_null_backend_frees_channels_when_sounds_end() {
+        let mixer = playing_mixer();
+        let mut backend = NullBackend::default();
+        backend.start(mixer.clone()).unwrap();
+        backend.advance(Duration::from_millis(5)).unwrap();
+        assert!(lock(&mixer).is_playing(1));
+        backend.advance(Duration::from_millis(5)).unwrap();
+        assert!(!lock(&mixer).is_playing(1));
+    }
+
+    #[test]
+    fn the_capture_holds_the_mixed_samples() {
+        let path = std::env::temp_dir().join(format!("doom-capture-{}.wav", std::process::id()));
+        let mixer = playing_mixer();
+        let mut backend = WavCaptureBackend::create(&path).unwrap();
+        backend.start(mixer).unwrap();
+        // 200 frames, the sound lasts for the first 100 of them
+        backend.advance(Duration::from_millis(20)).unwrap();
+        drop(backend);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let sample = |frame: usize, side: usize| {
+            let offset = WAV_HEADER_SIZE as usize + frame * 4 + side * 2;
+            i16::from_le_bytes([data[offset], data[offset + 1]])
+        };
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 200 * 4);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(24), RATE);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(40), 200 * 4);
+        assert_eq!(data.len(), WAV_HEADER_SIZE as usize + 200 * 4);
+        assert_eq!((sample(0, 0), sample(0, 1)), (32511, 0));
+        assert_eq!((sample(99, 0), sample(99, 1)), (32511, 0));
+        assert_eq!((sample(100, 0), sample(100, 1)), (0, 0));
+    }
+
+    #[test]
+    fn captures_too_long_for_a_wav_file_fail() {
+        let path = std::env::temp_dir().join(format!("doom-long-{}.wav", std::process::id()));
+        let mut backend = WavCaptureBackend::create(&path).unwrap();
+        backend.start(playing_mixer()).unwrap();
+        backend.data_size = u32::MAX - WAV_HEADER_SIZE;
+        let result = backend.advance(Duration::from_millis(10));
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use doom::audio::Sound;
+use doom::wad::{
+    Lump, WadError, WadFile, FLATS_END, FLATS_START, MAP_LUMP_NAMES, SPRITES_END, SPRITES_START,
+};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const USAGE: &str = "usage:
+  wadtool list <wad>
+  wadtool extract <wad> <lump> [output]
+  wadtool export <wad> <lump> [output]
+
+export writes pictures and flats as PNG, sounds as WAV and music as MIDI;
+PC speaker sounds are synthesized to a square wave";
+
+/// What a lump holds, guessed from its name, its place in the directory and its data
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum LumpKind {
+    Marker,
+    Map,
+    MapData,
+    Palette,
+    Colormap,
+    TextureDefs,
+    PatchNames,
+    Flat,
+    Sprite,
+    Picture,
+    Sound,
+    PcSound,
+    Music,
+    Midi,
+    Png,
+    Unknown,
+}
+
+impl fmt::Display for LumpKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LumpKind::Marker => "marker",
+            LumpKind::Map => "map",
+            LumpKind::MapData => "map data",
+            LumpKind::Palette => "palette",
+            LumpKind::Colormap => "colormap",
+            LumpKind::TextureDefs => "textures",
+            LumpKind::PatchNames => "patch names",
+            LumpKind::Flat => "flat",
+            LumpKind::Sprite => "sprite",
+            LumpKind::Picture => "picture",
+            LumpKind::Sound => "sound",
+            LumpKind::PcSound => "pc speaker sound",
+            LumpKind::Music => "music",
+            LumpKind::Midi => "midi",
+            LumpKind::Png => "png",
+            LumpKind::Unknown => "unknown",
+        };
+        f.pad(name)
+    }
+}
+
+/// The ranges of the sprite and flat namespaces
+struct Namespaces {
+    sprites: Option<Range<usize>>,
+    flats: Option<Range<usize>>,
+}
+
+impl Namespaces {
+    fn new(wad: &WadFile) -> Self {
+        Self {
+            sprites: wad.get_namespace_range(SPRITES_START, SPRITES_END),
+            flats: wad.get_namespace_range(FLATS_START, FLATS_END),
+        }
+    }
+
+    fn contains(range: &Option<Range<usize>>, index: usize) -> bool {
+        range.as_ref().is_some_and(|range| range.contains(&index))
+    }
+}
+
+/// Guesses what a lump holds
+fn detect_kind(wad: &WadFile, namespaces: &Namespaces, index: usize) -> Result<LumpKind, WadError> {
+    let lump = &wad.lumps[index];
+    let next_is_map_data = wad
+        .get_lump_by_index(index + 1)
+        .is_some_and(|next| next.name == MAP_LUMP_NAMES[0]);
+    if next_is_map_data {
+        return Ok(LumpKind::Map);
+    }
+    // Map lumps can be empty, like the REJECT lump of a map without one
+    if MAP_LUMP_NAMES.contains(&lump.name.as_str()) {
+        return Ok(LumpKind::MapData);
+    }
+    if lump.size() == 0 {
+        return Ok(LumpKind::Marker);
+    }
+
+    let kind = match lump.name.as_str() {
+        "PLAYPAL" => LumpKind::Palette,
+        "COLORMAP" => LumpKind::Colormap,
+        "TEXTURE1" | "TEXTURE2" => LumpKind::TextureDefs,
+        "PNAMES" => LumpKind::PatchNames,
+        _ if Namespaces::contains(&namespaces.flats, index) => LumpKind::Flat,
+        _ if Namespaces::contains(&namespaces.sprites, index) => LumpKind::Sprite,
+        _ => detect_data_kind(lump)?,
+    };
+    Ok(kind)
+}
+
+/// Guesses what a lump holds from its header alone
+/// # Remarks
+/// Only the first bytes are read, and for pictures the column offsets, so listing a large WAD
+/// file doesn't read every lump in full.
+fn detect_data_kind(lump: &Lump) -> Result<LumpKind, WadError> {
+    let header = lump.read_start(8)?;
+    let kind = match header.as_slice() {
+        [0x03, 0x00, ..] if header.len() >= 8 => LumpKind::Sound,
+        [0x00, 0x00, ..] if header.len() >= 4 && lump.name.starts_with("DP") => LumpKind::PcSound,
+        [b'M', b'U', b'S', 0x1A, ..] => LumpKind::Music,
+        [b'M', b'T', b'h', b'd', ..] => LumpKind::Midi,
+        [0x89, b'P', b'N', b'G', ..] => LumpKind::Png,
+        _ if is_picture(lump, &header)? => LumpKind::Picture,
+        _ => LumpKind::Unknown,
+    };
+    Ok(kind)
+}
+
+/// Returns true if the picture header of a lump makes sense: a plausible size and every column
+/// starting after the column offsets and inside the lump
+fn is_picture(lump: &Lump, header: &[u8]) -> Result<bool, WadError> {
+    let (width, height) = match header {
+        [w0, w1, h0, h1, _, _, _, _] => (
+            i16::from_le_bytes([*w0, *w1]),
+            i16::from_le_bytes([*h0, *h1]),
+        ),
+        _ => return Ok(false),
+    };
+    let columns_start = 8 + width.max(0) as usize * 4;
+    if width <= 0 || height <= 0 || columns_start >= lump.size() {
+        return Ok(false);
+    }
+
+    let offsets = lump.read_start(columns_start)?;
+    Ok(offsets[8..].chunks_exact(4).all(|offset| {
+        let offset = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize;
+        (columns_start..lump.size()).contains(&offset)
+    }))
+}
+
+/// Prints the directory of a WAD file
+/// # Remarks
+/// A lump that can't be read shows the error in its row, and the listing goes on.
+fn list(wad: &WadFile) {
+    let namespaces = Namespaces::new(wad);
+    println!(
+        "{:>5}  {:<8}  {:>9}  {:>10}  type",
+        "index", "name", "size", "offset"
+    );
+    for (index, lump) in wad.lumps.iter().enumerate() {
+        let offset = lump.offset().map(|offset| offset.to_string());
+        let kind = match detect_kind(wad, &namespaces, index) {
+            Ok(kind) => kind.to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+        println!(
+            "{:>5}  {:<8}  {:>9}  {:>10}  {}",
+            index,
+            lump.name,
+            lump.size(),
+            offset.as_deref().unwrap_or("-"),
+            kind
+        );
+    }
+}
+
+/// Writes the raw data of a lump to a file
+fn extract(wad: &WadFile, name: &str, output: &Path) -> Result<(), WadError> {
+    let data = wad.require_lump(name)?;
+    std::fs::write(output, data).map_err(|source| WadError::Io {
+        path: output.to_path_buf(),
+        source,
+    })
+}
+
+/// Converts a picture, flat, sound or song to a PNG, WAV or MIDI file
+fn export(wad: &WadFile, name: &str, output: Option<PathBuf>) -> Result<PathBuf, WadError> {
+    let index = wad
+        .get_lump_index(name)
+        .ok_or_else(|| WadError::MissingLump {
+            name: name.to_string(),
+        })?;
+    let name = wad.lumps[index].name.clone();
+    let output = |extension: &str| {
+        output.clone().unwrap_or_else(|| {
+            PathBuf::from(format!("{}.{}", name.to_ascii_lowercase(), extension))
+        })
+    };
+
+    let image = match detect_kind(wad, &Namespaces::new(wad), index)? {
+        LumpKind::Flat => wad.get_flat(&name)?.to_image(&wad.get_palette()?),
+        LumpKind::Sprite | LumpKind::Picture => wad.get_image(&name)?.to_image(&wad.get_palette()?),
+        LumpKind::Music => {
+            let path = output("mid");
+            std::fs::write(&path, wad.get_music(&name)?.to_midi()).map_err(|source| {
+                WadError::Io {
+                    path: path.clone(),
+                    source,
+                }
+            })?;
+            return Ok(path);
+        }
+        LumpKind::Sound => {
+            let path = output("wav");
+            write_wav(&path, &*wad.get_sound(&name)?)?;
+            return Ok(path);
+        }
+        LumpKind::PcSound => {
+            let path = output("wav");
+            write_wav(&path, &*wad.get_pc_sound(&name)?)?;
+            return Ok(path);
+        }
+        kind => {
+            return Err(WadError::UnsupportedFormat {
+                name,
+                offset: 0,
+                reason: format!("can't export a lump of type {}", kind),
+            })
+        }
+    };
+
+    let path = output("png");
+    image.save(&path).map_err(|e| WadError::Io {
+        path: path.clone(),
+        source: std::io::Error::other(e),
+    })?;
+    Ok(path)
+}
+
+/// Writes a digitized sound as an 8-bit mono WAV file
+/// # Remarks
+/// DMX sounds hold unsigned 8-bit samples, which is exactly what WAV's 8-bit PCM holds.
+fn write_wav(path: &Path, sound: &Sound) -> Result<(), WadError> {
+    let rate = sound.sample_rate;
+    let samples = &sound.samples;
+    // Chunks are padded to an even size
+    let padding = samples.len() % 2;
+
+    let mut wav = Vec::with_capacity(44 + samples.len() + padding);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&((36 + samples.len() + padding) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // Size of the format chunk
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&rate.to_le_bytes());
+    wav.extend_from_slice(&rate.to_le_bytes()); // Bytes per second
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Bytes per sample
+    wav.extend_from_slice(&8u16.to_le_bytes()); // Bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(samples);
+    wav.resize(wav.len() + padding, 0);
+
+    std::fs::write(path, wav).map_err(|source| WadError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Runs a command
+fn run(args: &[String]) -> Result<(), WadError> {
+    let load = |path: &String| WadFile::load(path);
+    match args {
+        [command, wad] if command == "list" => {
+            list(&load(wad)?);
+            Ok(())
+        }
+        [command, wad, lump, rest @ ..] if command == "extract" && rest.len() <= 1 => {
+            let output = rest
+                .first()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.lmp", lump.to_ascii_lowercase())));
+            extract(&load(wad)?, lump, &output)?;
+            println!("Extracted {} to {}", lump, output.display());
+            Ok(())
+        }
+        [command, wad, lump, rest @ ..] if command == "export" && rest.len() <= 1 => {
+            let output = export(&load(wad)?, lump, rest.first().map(PathBuf::from))?;
+            println!("Exported {} to {}", lump, output.display());
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    }
+}
+
+/// Lists, extracts and exports the lumps of a WAD file
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doom::wad::{encode_picture, Picture, WadKind, WadWriter};
+
+    fn picture_lump(width: usize, height: usize) -> Vec<u8> {
+        let picture = Picture {
+            width,
+            height,
+            left_offset: 0,
+            top_offset: 0,
+            pixels: vec![1; width * height],
+            opaque: vec![true; width * height],
+        };
+        encode_picture("PICTURE", &picture).unwrap()
+    }
+
+    /// Writes the lumps to a temporary WAD file and loads it back, so lumps are read from disk
+    fn load(test: &str, writer: &WadWriter) -> WadFile {
+        let path =
+            std::env::temp_dir().join(format!("wadtool_{}_{}.wad", test, std::process::id()));
+        writer.write(&path).unwrap();
+        let wad = WadFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        wad.unwrap()
+    }
+
+    #[test]
+    fn detect_kind_uses_names_namespaces_and_headers() {
+        let mut writer = WadWriter::new(WadKind::Pwad);
+        writer
+            .add_lump("PLAYPAL", vec![0; 768])
+            .unwrap()
+            .add_lump("PNAMES", vec![0; 4])
+            .unwrap()
+            .add_map("E1M1", vec![("THINGS", vec![0; 10])])
+            .unwrap()
+            .add_namespace("S_START", "S_END", vec![("TROOA1", picture_lump(2, 2))])
+            .unwrap()
+            .add_namespace("F_START", "F_END", vec![("FLOOR0_1", vec![0; 4096])])
+            .unwrap()
+            .add_lump(
+                "DSPISTOL",
+                [&[3, 0, 0x11, 0x2B, 1, 0, 0, 0][..], &[128]].concat(),
+            )
+            .unwrap()
+            .add_lump("DPPISTOL", vec![0, 0, 1, 0, 10])
+            .unwrap()
+            .add_lump("D_E1M1", b"MUS\x1a".to_vec())
+            .unwrap()
+            .add_lump("TITLEPIC", picture_lump(4, 2))
+            .unwrap()
+            .add_lump("ENDOOM", vec![0x20; 16])
+            .unwrap();
+        let wad = load("detect_kind", &writer);
+        let namespaces = Namespaces::new(&wad);
+        let kind = |name: &str| {
+            let index = wad.get_lump_index(name).unwrap();
+            detect_kind(&wad, &namespaces, index).unwrap()
+        };
+
+        assert_eq!(kind("PLAYPAL"), LumpKind::Palette);
+        assert_eq!(kind("PNAMES"), LumpKind::PatchNames);
+        assert_eq!(kind("E1M1"), LumpKind::Map);
+        assert_eq!(kind("THINGS"), LumpKind::MapData);
+        // Empty map lumps are still map data
+        assert_eq!(kind("REJECT"), LumpKind::MapData);
+        assert_eq!(kind("S_START"), LumpKind::Marker);
+        assert_eq!(kind("TROOA1"), LumpKind::Sprite);
+        assert_eq!(kind("FLOOR0_1"), LumpKind::Flat);
+        assert_eq!(kind("DSPISTOL"), LumpKind::Sound);
+        assert_eq!(kind("DPPISTOL"), LumpKind::PcSound);
+        assert_eq!(kind("D_E1M1"), LumpKind::Music);
+        assert_eq!(kind("TITLEPIC"), LumpKind::Picture);
+        assert_eq!(kind("ENDOOM"), LumpKind::Unknown);
+    }
+
+    #[test]
+    fn is_picture_checks_the_size_and_column_offsets() {
+        let is_picture = |data: Vec<u8>| {
+            let lump = Lump::new("TEST", data);
+            is_picture(&lump, &lump.read_start(8).unwrap()).unwrap()
+        };
+        assert!(is_picture(picture_lump(4, 3)));
+
+        // A column past the end of the lump
+        let mut data = picture_lump(4, 3);
+        let size = data.len() as u32;
+        data[12..16].copy_from_slice(&size.to_le_bytes());
+        assert!(!is_picture(data));
+
+        // A column inside the column offsets
+        let mut data = picture_lump(4, 3);
+        data[8..12].copy_from_slice(&8u32.to_le_bytes());
+        assert!(!is_picture(data));
+
+        let mut data = picture_lump(4, 3);
+        data[0..2].copy_from_slice(&0i16.to_le_bytes());
+        assert!(!is_picture(data));
+        assert!(!is_picture(vec![1, 0, 1, 0]));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::audio::Sound;
+use crate::flat::Flat;
+use crate::genmidi::GenMidi;
+use crate::music::Mus;
+use crate::texture::TextureSet;
+use crate::wad::{Palette, Patch, Picture, WadError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Decoded resources of a single kind, keyed by the directory index of the lump they came from
+/// # Remarks
+/// The values are reference counted, so handing one out is cheap and the cache can be cleared
+/// while the game still holds on to some of them.
+pub struct LumpCache<T> {
+    entries: RefCell<HashMap<usize, T>>,
+}
+
+impl<T> Default for LumpCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> LumpCache<T> {
+    /// Gets a resource, decoding and storing it on first use
+    /// # Arguments
+    /// * `index` - The directory index of the lump
+    /// * `decode` - Decodes the resource when it is not cached yet
+    /// # Remarks
+    /// Errors are not cached, so decoding a broken lump is retried on the next call.
+    pub fn get_or_decode(
+        &self,
+        index: usize,
+        decode: impl FnOnce() -> Result<T, WadError>,
+    ) -> Result<T, WadError> {
+        if let Some(value) = self.entries.borrow().get(&index) {
+            return Ok(value.clone());
+        }
+
+        // The decoder may use other caches, so nothing is borrowed while it runs
+        let value = decode()?;
+        self.entries.borrow_mut().insert(index, value.clone());
+        Ok(value)
+    }
+
+    /// The amount of cached resources
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+}
+
+/// The decoded resources of a WAD file
+/// # Remarks
+/// Everything is decoded lazily, the first time it is asked for. The cache is thrown away as a
+/// whole when the lumps change, e.g. when a PWAD is added, since a PWAD can replace any lump and
+/// move the others around in the directory.
+#[derive(Default)]
+pub struct ResourceCache {
+    /// The palettes of PLAYPAL
+    pub palettes: LumpCache<Rc<[Palette]>>,
+    /// Pictures decoded to their posts, as used to composite textures
+    pub patches: LumpCache<Rc<Patch>>,
+    /// Pictures decoded to palette indices, as drawn on screen
+    pub pictures: LumpCache<Rc<Picture>>,
+    pub flats: LumpCache<Rc<Flat>>,
+    /// Digitized sounds and synthesized PC speaker sounds, shared with the audio thread
+    pub sounds: LumpCache<Arc<Sound>>,
+    /// Songs, shared with the audio thread
+    pub music: LumpCache<Arc<Mus>>,
+    /// The OPL instrument bank, shared with the audio thread
+    pub genmidi: LumpCache<Arc<GenMidi>>,
+    /// The texture definitions and composited textures, loaded on first use
+    pub textures: RefCell<Option<TextureSet>>,
+}
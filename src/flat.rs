@@ -0,0 +1,45 @@
+use crate::image_tools::indexed_to_image;
+use crate::wad::WadError;
+use image::DynamicImage;
+
+pub const FLAT_WIDTH: usize = 64;
+pub const FLAT_HEIGHT: usize = 64;
+const FLAT_SIZE: usize = FLAT_WIDTH * FLAT_HEIGHT;
+
+/// A floor or ceiling texture
+/// # Remarks
+/// Flats are stored as raw 64x64 palette indices, row by row, without any header.
+pub struct Flat {
+    pub name: String,
+    /// The palette indices, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl Flat {
+    /// Decodes a flat lump
+    /// # Remarks
+    /// Some PWADs append extra rows to their flats; only the first 64 rows are used.
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, WadError> {
+        if data.len() < FLAT_SIZE {
+            return Err(WadError::TruncatedLump {
+                name: name.to_string(),
+                offset: data.len(),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            pixels: data[..FLAT_SIZE].to_vec(),
+        })
+    }
+
+    /// Gets the palette index at a position, wrapping around like the floor renderer does
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[(y % FLAT_HEIGHT) * FLAT_WIDTH + x % FLAT_WIDTH]
+    }
+
+    /// Converts the flat to an RGB image using the given palette
+    pub fn to_image(&self, palette: &[[u8; 3]]) -> DynamicImage {
+        indexed_to_image(&self.pixels, None, FLAT_WIDTH, FLAT_HEIGHT, palette)
+    }
+}
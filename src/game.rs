@@ -1,9 +1,12 @@
+use crate::audio::{SfxDevice, SoundSystem};
+use crate::game_mode::GameMode;
+use crate::level::Level;
 use crate::menu::Menu;
-use crate::wad::WadFile;
-use image::DynamicImage;
+use crate::mixer::{Origin, MAX_CHANNEL_VOLUME};
+use crate::sounds::{SfxId, SfxTable};
+use crate::wad::{Picture, WadError, WadFile};
+use std::rc::Rc;
 
-use crate::HEIGHT;
-use crate::WIDTH;
 const SKULL_LUMP_NAME: &str = "M_SKULL1";
 const BACKGROUND_LUMP_NAME: &str = "TITLEPIC";
 
@@ -24,17 +27,26 @@ pub enum GameState {
 
 /// The game struct
 /// # Remarks
-/// This struct is used to store the game state, the menu, the WAD file, the episode, the skill
-/// level, and the background and skull images.
+/// This struct is used to store the game state, the menu, the WAD file and its game mode, the
+/// episode, the map, the skill level, the loaded level, the sound system, the sound effect device
+/// and table, and the background and skull images.
 /// This also contains the menu struct, which is used to store the current menu state.
 pub struct Game {
     pub state: GameState,
     pub menu: Menu,
     pub wad: WadFile,
+    pub mode: GameMode,
     pub episode: usize,
+    pub map: usize,
     pub skill: Skill,
-    pub skull: DynamicImage,
-    pub background: DynamicImage,
+    pub level: Option<Level>,
+    pub sound: SoundSystem,
+    /// Whether the digitized or the PC speaker sound effects are played
+    pub sfx_device: SfxDevice,
+    /// How each sound effect is played
+    pub sfx: SfxTable,
+    pub skull: Rc<Picture>,
+    pub background: Rc<Picture>,
 }
 
 /// The possible skill levels
@@ -53,33 +65,106 @@ pub enum Skill {
 /// The game implementation
 impl Game {
     /// Creates a new game
+    /// # Arguments
+    /// * `wad` - The WAD file, including any PWADs
+    /// * `mode` - The game mode detected from the IWAD
     /// # Examples
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let game = Game::new(wad, mode);
     /// ```
-    /// use game::Game;
-    /// use wad::WadFile;
-    /// let wad = WadFile::new("doom1.wad");
-    /// let game = Game::new(wad);
-    /// ```
-    pub fn new(wad: WadFile) -> Self {
+    pub fn new(wad: WadFile, mode: GameMode) -> Self {
         let background = wad
             .get_image(BACKGROUND_LUMP_NAME)
-            .expect("Background image not found")
-            .resize_exact(
-                WIDTH as u32,
-                HEIGHT as u32,
-                image::imageops::FilterType::Nearest,
-            );
+            .expect("Background image not found");
 
         let skull = wad.get_image(SKULL_LUMP_NAME).unwrap();
 
-        Self {
+        let game = Self {
             state: GameState::Menu,
             menu: Menu::root(&wad),
             wad,
+            mode,
             episode: 1,
+            map: 1,
             background,
             skull,
             skill: Skill::TooYoungToDie,
+            level: None,
+            sound: SoundSystem::new(),
+            sfx_device: SfxDevice::default(),
+            sfx: SfxTable::default(),
+        };
+        game.play_music(game.mode.title_music_name(), false);
+        game
+    }
+
+    /// Starts a sound effect at full volume, straight ahead
+    /// # Arguments
+    /// * `id` - The sound effect, looked up in the sound table
+    /// * `origin` - What makes the sound; a new sound from the same origin replaces the old one
+    /// # Returns
+    /// The channel the sound plays on, or `None` if it isn't played
+    /// # Remarks
+    /// Like vanilla's S_StartSound, a linked sound plays the lumps of its link with its own
+    /// priority, and its volume adjustment can only make it quieter. A missing lump is reported
+    /// and the sound is skipped, as in Chocolate Doom.
+    /// # Examples
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game_mode::GameMode;
+    /// use doom::sounds::SfxId;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let game = Game::new(wad, mode);
+    /// game.start_sound(SfxId::Pistol, None);
+    /// ```
+    pub fn start_sound(&self, id: SfxId, origin: Option<Origin>) -> Option<usize> {
+        if id == SfxId::None {
+            return None;
+        }
+        let sfx = self.sfx.resolve(id);
+        let master = self.sound.sfx_volume();
+        let volume = (master + sfx.volume).min(master);
+        if volume < 1 {
+            return None;
+        }
+
+        let sound = match self.wad.get_sfx(sfx.name, self.sfx_device) {
+            Ok(sound) => sound,
+            Err(e) => {
+                eprintln!("Unable to play sound: {}", e);
+                return None;
+            }
+        };
+        self.sound.start_sound(
+            sound,
+            sfx.priority,
+            origin,
+            MAX_CHANNEL_VOLUME * volume / master,
+        )
+    }
+
+    /// Starts playing a music lump
+    /// # Arguments
+    /// * `name` - The name of the music lump, e.g. `D_E1M1`
+    /// * `looping` - Whether the song starts over when it ends
+    /// # Remarks
+    /// A missing or broken song is reported and the game goes on without music, as PWADs
+    /// sometimes replace songs with formats that can't be played.
+    pub fn play_music(&self, name: &str, looping: bool) {
+        let song = self.wad.get_music(name);
+        match song.and_then(|song| Ok((song, self.wad.get_genmidi()?))) {
+            Ok((song, bank)) => self.sound.play_music(song, bank, looping),
+            Err(e) => {
+                eprintln!("Unable to play music: {}", e);
+                self.sound.stop_music();
+            }
         }
     }
 
@@ -87,12 +172,14 @@ impl Game {
     /// # Arguments
     /// * `game_state` - The game state to set
     /// # Examples
-    /// ```
-    /// use game::Game;
-    /// use game::GameState;
-    /// use wad::WadFile;
-    /// let wad = WadFile::new("doom1.wad");
-    /// let mut game = Game::new(wad);
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game::GameState;
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let mut game = Game::new(wad, mode);
     /// game.set_state(GameState::Playing);
     /// ```
     pub fn set_state(&mut self, game_state: GameState) {
@@ -103,12 +190,13 @@ impl Game {
     /// # Arguments
     /// * `episode` - The episode to set
     /// # Examples
-    /// ```
-    /// use game::Game;
-    /// use game::Episode;
-    /// use wad::WadFile;
-    /// let wad = WadFile::new("doom1.wad");
-    /// let mut game = Game::new(wad);
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let mut game = Game::new(wad, mode);
     /// game.set_episode(2);
     /// ```
     pub fn set_episode(&mut self, episode: usize) {
@@ -119,15 +207,40 @@ impl Game {
     /// # Arguments
     /// * `skill` - The skill level to set
     /// # Examples
-    /// ```
-    /// use game::Game;
-    /// use game::Skill;
-    /// use wad::WadFile;
-    /// let wad = WadFile::new("doom1.wad");
-    /// let mut game = Game::new(wad);
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game::Skill;
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let mut game = Game::new(wad, mode);
     /// game.set_skill(Skill::HurtMePlenty);
     /// ```
     pub fn set_skill(&mut self, skill: Skill) {
         self.skill = skill;
     }
+
+    /// Loads the level for the current episode and map
+    /// # Returns
+    /// An error if the map is not part of the WAD file or its lumps are malformed
+    /// # Examples
+    /// ```no_run
+    /// use doom::game::Game;
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mode = GameMode::detect(&wad).unwrap();
+    /// let mut game = Game::new(wad, mode);
+    /// game.set_episode(1);
+    /// game.load_level().unwrap();
+    /// assert_eq!(game.level.as_ref().unwrap().name, "E1M1");
+    /// ```
+    pub fn load_level(&mut self) -> Result<(), WadError> {
+        let name = self.mode.map_name(self.episode, self.map);
+        self.level = Some(Level::load(&self.wad, &name)?);
+        self.sound.set_boss_map(self.map == 8);
+        self.play_music(&self.mode.music_name(self.episode, self.map), true);
+        Ok(())
+    }
 }
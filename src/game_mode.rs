@@ -0,0 +1,206 @@
+use crate::wad::WadFile;
+
+/// The songs of the commercial games, by map number
+const COMMERCIAL_MUSIC: [&str; 32] = [
+    "RUNNIN", "STALKS", "COUNTD", "BETWEE", "DOOM", "THE_DA", "SHAWN", "DDTBLU", "IN_CIT", "DEAD",
+    "STLKS2", "THEDA2", "DOOM2", "DDTBL2", "RUNNI2", "DEAD2", "STLKS3", "ROMERO", "SHAWN2",
+    "MESSAG", "COUNT2", "DDTBL3", "AMPIE", "THEDA3", "ADRIAN", "MESSG2", "ROMER2", "TENSE",
+    "SHAWN3", "OPENIN", "EVIL", "ULTIMA",
+];
+/// The songs of episode 4, which reuses songs of the other episodes
+const EPISODE4_MUSIC: [&str; 9] = [
+    "E3M4", "E3M2", "E3M3", "E1M5", "E2M7", "E2M4", "E2M6", "E2M5", "E1M9",
+];
+
+/// The game the loaded IWAD belongs to
+/// # Remarks
+/// The game mode determines how maps are named (ExMy or MAPxx), how many episodes there are and
+/// whether the episode menu is shown at all.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum GameMode {
+    /// The shareware release (doom1.wad), only episode 1
+    Shareware,
+    /// The registered release (doom.wad), episodes 1 to 3
+    Registered,
+    /// The Ultimate Doom (doom.wad), episodes 1 to 4
+    Ultimate,
+    /// Doom II: Hell on Earth (doom2.wad)
+    Doom2,
+    /// Final Doom: TNT Evilution (tnt.wad)
+    Tnt,
+    /// Final Doom: The Plutonia Experiment (plutonia.wad)
+    Plutonia,
+    /// Freedoom: Phase 1 (freedoom1.wad), episodes 1 to 4
+    Freedoom1,
+    /// Freedoom: Phase 2 (freedoom2.wad) and FreeDM (freedm.wad)
+    Freedoom2,
+}
+
+impl GameMode {
+    /// Detects the game mode by looking at the lumps of an IWAD
+    /// # Arguments
+    /// * `wad` - The IWAD, before any PWADs are layered on top of it
+    /// # Returns
+    /// The game mode, or `None` if the WAD file contains no maps at all
+    /// # Examples
+    /// ```no_run
+    /// use doom::game_mode::GameMode;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// assert_eq!(GameMode::detect(&wad), Some(GameMode::Shareware));
+    /// ```
+    pub fn detect(wad: &WadFile) -> Option<Self> {
+        let has = |name: &str| wad.get_lump_index(name).is_some();
+        let freedoom = has("FREEDOOM") || has("FREEDM");
+
+        let mode = if has("MAP01") {
+            // TNT and Plutonia are told apart by lumps that only ship with them
+            if freedoom {
+                GameMode::Freedoom2
+            } else if has("REDTNT2") {
+                GameMode::Tnt
+            } else if has("CAMO1") {
+                GameMode::Plutonia
+            } else {
+                GameMode::Doom2
+            }
+        } else if has("E1M1") {
+            if freedoom {
+                GameMode::Freedoom1
+            } else if has("E4M1") {
+                GameMode::Ultimate
+            } else if has("E2M1") {
+                GameMode::Registered
+            } else {
+                GameMode::Shareware
+            }
+        } else {
+            return None;
+        };
+
+        Some(mode)
+    }
+
+    /// Returns true for the games that use MAPxx maps without episodes
+    pub fn is_commercial(&self) -> bool {
+        matches!(
+            self,
+            GameMode::Doom2 | GameMode::Tnt | GameMode::Plutonia | GameMode::Freedoom2
+        )
+    }
+
+    /// The amount of episodes to choose from in the episode menu
+    /// # Remarks
+    /// The commercial games have no episodes; they always start at MAP01.
+    pub fn episode_count(&self) -> usize {
+        match self {
+            GameMode::Shareware => 1,
+            GameMode::Registered => 3,
+            GameMode::Ultimate | GameMode::Freedoom1 => 4,
+            GameMode::Doom2 | GameMode::Tnt | GameMode::Plutonia | GameMode::Freedoom2 => 0,
+        }
+    }
+
+    /// The amount of maps in a single episode
+    pub fn maps_per_episode(&self) -> usize {
+        match self.is_commercial() {
+            true => 32,
+            false => 9,
+        }
+    }
+
+    /// Gets the name of the map marker for an episode and map number
+    /// # Examples
+    /// ```
+    /// use doom::game_mode::GameMode;
+    /// assert_eq!(GameMode::Ultimate.map_name(4, 2), "E4M2");
+    /// assert_eq!(GameMode::Doom2.map_name(1, 7), "MAP07");
+    /// ```
+    pub fn map_name(&self, episode: usize, map: usize) -> String {
+        match self.is_commercial() {
+            true => format!("MAP{:02}", map),
+            false => format!("E{}M{}", episode, map),
+        }
+    }
+
+    /// Gets the name of the music lump for an episode and map number
+    /// # Remarks
+    /// Like vanilla, episode 4 plays songs of the other episodes since it has none of its own.
+    /// # Examples
+    /// ```
+    /// use doom::game_mode::GameMode;
+    /// assert_eq!(GameMode::Registered.music_name(1, 1), "D_E1M1");
+    /// assert_eq!(GameMode::Ultimate.music_name(4, 1), "D_E3M4");
+    /// assert_eq!(GameMode::Doom2.music_name(1, 1), "D_RUNNIN");
+    /// ```
+    pub fn music_name(&self, episode: usize, map: usize) -> String {
+        let index = map.clamp(1, self.maps_per_episode()) - 1;
+        match self.is_commercial() {
+            true => format!("D_{}", COMMERCIAL_MUSIC[index]),
+            false if episode == 4 => format!("D_{}", EPISODE4_MUSIC[index]),
+            false => format!("D_E{}M{}", episode, map),
+        }
+    }
+
+    /// Gets the name of the music lump played on the title screen
+    pub fn title_music_name(&self) -> &'static str {
+        match self.is_commercial() {
+            true => "D_DM2TTL",
+            false => "D_INTRO",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::Lump;
+
+    fn detect(names: &[&str]) -> Option<GameMode> {
+        let lumps = names
+            .iter()
+            .map(|name| Lump::new(name, Vec::new()))
+            .collect();
+        GameMode::detect(&WadFile::from_lumps(lumps))
+    }
+
+    #[test]
+    fn detects_the_episodic_games() {
+        assert_eq!(detect(&["E1M1"]), Some(GameMode::Shareware));
+        assert_eq!(
+            detect(&["E1M1", "E2M1", "E3M1"]),
+            Some(GameMode::Registered)
+        );
+        assert_eq!(
+            detect(&["E1M1", "E2M1", "E3M1", "E4M1"]),
+            Some(GameMode::Ultimate)
+        );
+        assert_eq!(
+            detect(&["FREEDOOM", "E1M1", "E2M1", "E3M1", "E4M1"]),
+            Some(GameMode::Freedoom1)
+        );
+    }
+
+    #[test]
+    fn detects_the_commercial_games() {
+        assert_eq!(detect(&["MAP01"]), Some(GameMode::Doom2));
+        assert_eq!(detect(&["MAP01", "REDTNT2"]), Some(GameMode::Tnt));
+        assert_eq!(detect(&["MAP01", "CAMO1"]), Some(GameMode::Plutonia));
+        assert_eq!(detect(&["FREEDOOM", "MAP01"]), Some(GameMode::Freedoom2));
+        assert_eq!(detect(&["FREEDM", "MAP01"]), Some(GameMode::Freedoom2));
+    }
+
+    #[test]
+    fn wads_without_maps_have_no_game_mode() {
+        assert_eq!(detect(&[]), None);
+        assert_eq!(detect(&["PLAYPAL", "TITLEPIC"]), None);
+    }
+
+    #[test]
+    fn shareware_has_a_single_episode() {
+        assert_eq!(GameMode::Shareware.episode_count(), 1);
+        assert_eq!(GameMode::Registered.episode_count(), 3);
+        assert_eq!(GameMode::Ultimate.episode_count(), 4);
+        assert_eq!(GameMode::Doom2.episode_count(), 0);
+    }
+}
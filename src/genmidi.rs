@@ -0,0 +1,278 @@
+use crate::wad::{LumpReader, WadError};
+
+const GENMIDI_SIGNATURE: &[u8] = b"#OPL_II#";
+/// The amount of instruments in the bank: the 128 General MIDI programs and 47 percussion notes
+pub const GENMIDI_NUM_INSTRUMENTS: usize = 175;
+const GENMIDI_NUM_MELODIC: usize = 128;
+/// The first and last percussion notes that have an instrument
+pub const GENMIDI_FIRST_PERCUSSION: u8 = 35;
+pub const GENMIDI_LAST_PERCUSSION: u8 = 81;
+/// The instrument always plays `fixed_note`, whatever note is asked for
+pub const GENMIDI_FLAG_FIXED: u16 = 0x0001;
+/// The instrument plays both of its voices at once
+pub const GENMIDI_FLAG_DOUBLE_VOICE: u16 = 0x0004;
+/// The names follow the instruments and are 32 bytes each
+const GENMIDI_NAME_LENGTH: usize = 32;
+
+/// The register values of one operator, in the order they are stored in the lump
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct GenMidiOperator {
+    /// Tremolo, vibrato, sustain, key scale rate and multiplier, register 0x20
+    pub tremolo: u8,
+    /// Attack and decay rates, register 0x60
+    pub attack: u8,
+    /// Sustain level and release rate, register 0x80
+    pub sustain: u8,
+    /// The waveform, register 0xE0
+    pub waveform: u8,
+    /// The key scale level, the high two bits of register 0x40
+    pub scale: u8,
+    /// The output level, the low six bits of register 0x40
+    pub level: u8,
+}
+
+/// The settings of an OPL channel for one voice of an instrument
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct GenMidiVoice {
+    pub modulator: GenMidiOperator,
+    /// Feedback and connection, register 0xC0
+    pub feedback: u8,
+    pub carrier: GenMidiOperator,
+    /// The amount of semitones the notes are moved by
+    pub base_note_offset: i16,
+}
+
+/// An instrument of the GENMIDI bank
+#[derive(Debug, PartialEq, Clone)]
+pub struct GenMidiInstrument {
+    pub name: String,
+    /// See `GENMIDI_FLAG_FIXED` and `GENMIDI_FLAG_DOUBLE_VOICE`
+    pub flags: u16,
+    /// The detune of the second voice; 128 is in tune
+    pub fine_tuning: u8,
+    /// The note played by instruments with a fixed pitch
+    pub fixed_note: u8,
+    pub voices: [GenMidiVoice; 2],
+}
+
+impl GenMidiInstrument {
+    pub fn is_fixed(&self) -> bool {
+        self.flags & GENMIDI_FLAG_FIXED != 0
+    }
+
+    pub fn is_double_voice(&self) -> bool {
+        self.flags & GENMIDI_FLAG_DOUBLE_VOICE != 0
+    }
+}
+
+/// The OPL instrument bank of the GENMIDI lump
+/// # Remarks
+/// The lump starts with `#OPL_II#`, followed by 175 instruments of 36 bytes and then their
+/// names. The first 128 instruments are the General MIDI programs, the others the percussion
+/// notes 35 to 81 of MIDI channel 9 (MUS channel 15).
+/// # Examples
+/// ```no_run
+/// use doom::wad::WadFile;
+/// let wad = WadFile::load("doom1.wad").unwrap();
+/// let bank = wad.get_genmidi().unwrap();
+/// println!("{}", bank.melodic(0).name);
+/// ```
+pub struct GenMidi {
+    pub instruments: Vec<GenMidiInstrument>,
+}
+
+impl GenMidi {
+    /// Decodes the GENMIDI lump
+    /// # Returns
+    /// The bank, an `UnsupportedFormat` error if the signature is missing, or a `TruncatedLump`
+    /// error if the lump is too short for all instruments
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, WadError> {
+        if !data.starts_with(GENMIDI_SIGNATURE) {
+            return Err(WadError::UnsupportedFormat {
+                name: name.to_string(),
+                offset: 0,
+                reason: "not an OPL instrument bank".to_string(),
+            });
+        }
+
+        let mut reader = LumpReader::new(name, data);
+        reader.set_position(GENMIDI_SIGNATURE.len());
+        let mut instruments = (0..GENMIDI_NUM_INSTRUMENTS)
+            .map(|_| read_instrument(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Some banks leave the names out
+        if reader.remaining() >= GENMIDI_NUM_INSTRUMENTS * GENMIDI_NAME_LENGTH {
+            for instrument in instruments.iter_mut() {
+                let name = reader.read_bytes(GENMIDI_NAME_LENGTH)?;
+                // Unlike lump names, the instrument names are mixed case
+                instrument.name = name
+                    .iter()
+                    .take_while(|&&byte| byte != 0)
+                    .map(|&byte| byte as char)
+                    .collect();
+            }
+        }
+        Ok(Self { instruments })
+    }
+
+    /// Gets the instrument of a General MIDI program
+    pub fn melodic(&self, program: u8) -> &GenMidiInstrument {
+        &self.instruments[program as usize % GENMIDI_NUM_MELODIC]
+    }
+
+    /// Gets the instrument of a percussion note, or `None` for notes outside 35 to 81
+    pub fn percussion(&self, note: u8) -> Option<&GenMidiInstrument> {
+        match (GENMIDI_FIRST_PERCUSSION..=GENMIDI_LAST_PERCUSSION).contains(&note) {
+            true => Some(
+                &self.instruments[GENMIDI_NUM_MELODIC + (note - GENMIDI_FIRST_PERCUSSION) as usize],
+            ),
+            false => None,
+        }
+    }
+}
+
+fn read_instrument(reader: &mut LumpReader) -> Result<GenMidiInstrument, WadError> {
+    let flags = reader.read_u16()?;
+    let fine_tuning = reader.read_u8()?;
+    let fixed_note = reader.read_u8()?;
+    let voices = [read_voice(reader)?, read_voice(reader)?];
+    Ok(GenMidiInstrument {
+        name: String::new(),
+        flags,
+        fine_tuning,
+        fixed_note,
+        voices,
+    })
+}
+
+fn read_voice(reader: &mut LumpReader) -> Result<GenMidiVoice, WadError> {
+    let modulator = read_operator(reader)?;
+    let feedback = reader.read_u8()?;
+    let carrier = read_operator(reader)?;
+    reader.read_u8()?; // Unused
+    let base_note_offset = reader.read_i16()?;
+    Ok(GenMidiVoice {
+        modulator,
+        feedback,
+        carrier,
+        base_note_offset,
+    })
+}
+
+fn read_operator(reader: &mut LumpReader) -> Result<GenMidiOperator, WadError> {
+    Ok(GenMidiOperator {
+        tremolo: reader.read_u8()?,
+        attack: reader.read_u8()?,
+        sustain: reader.read_u8()?,
+        waveform: reader.read_u8()?,
+        scale: reader.read_u8()?,
+        level: reader.read_u8()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The size of an instrument in the lump
+    const INSTRUMENT_SIZE: usize = 36;
+
+    /// Builds a bank where every instrument is empty except the given ones
+    fn bank(instruments: &[(usize, [u8; INSTRUMENT_SIZE])], names: bool) -> Vec<u8> {
+        let mut data = GENMIDI_SIGNATURE.to_vec();
+        let start = data.len();
+        data.resize(start + GENMIDI_NUM_INSTRUMENTS * INSTRUMENT_SIZE, 0);
+        for (index, instrument) in instruments {
+            let offset = start + index * INSTRUMENT_SIZE;
+            data[offset..offset + INSTRUMENT_SIZE].copy_from_slice(instrument);
+        }
+        if names {
+            for index in 0..GENMIDI_NUM_INSTRUMENTS {
+                let mut name = format!("Instrument {}", index).into_bytes();
+                name.resize(GENMIDI_NAME_LENGTH, 0);
+                data.extend(name);
+            }
+        }
+        data
+    }
+
+    /// A double voice instrument with a fixed note, whose register values count up
+    fn instrument() -> [u8; INSTRUMENT_SIZE] {
+        let mut instrument = [0; INSTRUMENT_SIZE];
+        instrument[..4].copy_from_slice(&[0x05, 0x00, 130, 60]);
+        for (offset, value) in instrument[4..].iter_mut().enumerate() {
+            *value = offset as u8 + 1;
+        }
+        // A base note offset of -12 for the first voice
+        instrument[18..20].copy_from_slice(&(-12i16).to_le_bytes());
+        instrument
+    }
+
+    #[test]
+    fn decodes_the_instruments() {
+        let bank = GenMidi::decode("GENMIDI", &bank(&[(3, instrument())], false)).unwrap();
+        assert_eq!(bank.instruments.len(), GENMIDI_NUM_INSTRUMENTS);
+        let instrument = bank.melodic(3);
+        assert!(instrument.is_fixed() && instrument.is_double_voice());
+        assert_eq!((instrument.fine_tuning, instrument.fixed_note), (130, 60));
+        let voice = instrument.voices[0];
+        assert_eq!(
+            voice.modulator,
+            GenMidiOperator {
+                tremolo: 1,
+                attack: 2,
+                sustain: 3,
+                waveform: 4,
+                scale: 5,
+                level: 6,
+            }
+        );
+        assert_eq!(voice.feedback, 7);
+        assert_eq!(voice.carrier.tremolo, 8);
+        assert_eq!(voice.carrier.level, 13);
+        assert_eq!(voice.base_note_offset, -12);
+        assert_eq!(instrument.voices[1].modulator.tremolo, 17);
+        assert!(!bank.melodic(4).is_fixed());
+    }
+
+    #[test]
+    fn percussion_notes_follow_the_programs() {
+        let bank = GenMidi::decode("GENMIDI", &bank(&[(128, instrument())], false)).unwrap();
+        assert!(bank
+            .percussion(GENMIDI_FIRST_PERCUSSION)
+            .unwrap()
+            .is_fixed());
+        assert!(!bank.percussion(GENMIDI_LAST_PERCUSSION).unwrap().is_fixed());
+        assert!(bank.percussion(GENMIDI_FIRST_PERCUSSION - 1).is_none());
+        assert!(bank.percussion(GENMIDI_LAST_PERCUSSION + 1).is_none());
+    }
+
+    #[test]
+    fn names_are_optional() {
+        let bank_without_names = GenMidi::decode("GENMIDI", &bank(&[], false)).unwrap();
+        assert!(bank_without_names.melodic(0).name.is_empty());
+        let bank_with_names = GenMidi::decode("GENMIDI", &bank(&[], true)).unwrap();
+        assert_eq!(bank_with_names.melodic(0).name, "Instrument 0");
+        assert_eq!(bank_with_names.instruments[174].name, "Instrument 174");
+    }
+
+    #[test]
+    fn rejects_lumps_without_the_signature() {
+        let mut data = bank(&[], false);
+        data[0] = b'!';
+        assert!(matches!(
+            GenMidi::decode("GENMIDI", &data),
+            Err(WadError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_banks() {
+        let data = bank(&[], false);
+        assert!(matches!(
+            GenMidi::decode("GENMIDI", &data[..data.len() - 1]),
+            Err(WadError::TruncatedLump { .. })
+        ));
+    }
+}
@@ -1,48 +1,76 @@
-use crate::WIDTH;
-use image::{DynamicImage, GenericImageView};
+use image::{DynamicImage, ImageBuffer, Rgb, Rgba};
 
-/// Returns true if the pixel is black
+/// Converts palette indices to an image
+/// # Arguments
+/// * `pixels` - The palette indices, row by row
+/// * `mask` - Which pixels are opaque, row by row, or `None` if they all are
+/// * `width` - The width of the image
+/// * `height` - The height of the image
+/// * `palette` - The palette to look the colors up in
+/// # Returns
+/// An RGBA image when a mask is given, with the masked out pixels fully transparent, and an RGB
+/// image otherwise
 /// # Examples
 /// ```
-/// use image::Rgba;
-/// use image_tools::is_black;
-/// assert_eq!(is_black(&Rgba([0, 0, 0, 255])), true);
-/// assert_eq!(is_black(&Rgba([255, 255, 255, 255])), false);
+/// use doom::image_tools::indexed_to_image;
+/// let palette = vec![[0, 0, 0], [255, 255, 255]];
+/// let img = indexed_to_image(&[0, 1, 1, 0], Some(&[true, true, true, false]), 2, 2, &palette);
+/// assert_eq!(img.width(), 2);
 /// ```
-///
-fn is_black(pixel: &image::Rgba<u8>) -> bool {
-    pixel[0] == 0 && pixel[1] == 0 && pixel[2] == 0
+pub fn indexed_to_image(
+    pixels: &[u8],
+    mask: Option<&[bool]>,
+    width: usize,
+    height: usize,
+    palette: &[[u8; 3]],
+) -> DynamicImage {
+    let color = |x: u32, y: u32| palette[pixels[y as usize * width + x as usize] as usize];
+    match mask {
+        Some(mask) => {
+            DynamicImage::ImageRgba8(ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+                let [r, g, b] = color(x, y);
+                let alpha = match mask[y as usize * width + x as usize] {
+                    true => 255,
+                    false => 0,
+                };
+                Rgba([r, g, b, alpha])
+            }))
+        }
+        None => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+                Rgb(color(x, y))
+            }))
+        }
+    }
 }
 
-/// Renders an image to the screen buffer
-/// # Examples
-/// ```
-/// use image::DynamicImage;
-/// use image_tools::render_image;
-/// let img = DynamicImage::new_rgb8(100, 100);
-/// let mut buffer = vec![0; 100 * 100];
-/// render_image(&img, 0, 0, &mut buffer);
-/// assert_ne!(buffer[0], 0);
-/// ```
-pub fn render_image(img: &DynamicImage, x_pos: usize, y_pos: usize, buffer: &mut [u32]) {
-    let length = &buffer.len();
-    img.pixels()
-        // filter out black pixels
-        .filter(|(_, _, pixel)| !is_black(&pixel))
-        // map x and y to the position in the buffer
-        .map(|(x, y, pixel)| (((y as usize + y_pos) * WIDTH) + (x as usize + x_pos), pixel))
-        // filter out pixels that are outside the buffer
-        .filter(|(pos, _)| &pos < &length)
-        // convert the pixel to a color and write it to the buffer
-        .for_each(|(pos, pixel)| buffer[pos] = convert_to_color(&pixel));
+/// Converts a color to the ARGB format of the window buffer
+/// # Arguments
+/// * `rgb` - The color to convert
+/// # Returns
+/// The color as a u32
+pub fn convert_to_color(rgb: [u8; 3]) -> u32 {
+    (0xFF << 24) | ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32
 }
 
-/// Converts a pixel to a color
+/// Finds the palette index of the color closest to an RGB color
 /// # Arguments
-/// * `pixel` - The pixel to convert as an image::Rgba<u8>.
+/// * `rgb` - The color to look up
+/// * `palette` - The palette to search
 /// # Returns
-/// The color of the pixel as a u32
-fn convert_to_color(pixel: &image::Rgba<u8>) -> u32 {
-    let rgba = pixel.0;
-    ((rgba[3] as u32) << 24) | ((rgba[0] as u32) << 16) | ((rgba[1] as u32) << 8) | rgba[2] as u32
+/// The index of the palette color with the smallest squared distance; the first one wins a tie
+pub fn nearest_color(rgb: [u8; 3], palette: &[[u8; 3]]) -> u8 {
+    let distance = |color: &[u8; 3]| {
+        color
+            .iter()
+            .zip(rgb)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, color)| distance(color))
+        .map(|(index, _)| index as u8)
+        .unwrap_or(0)
 }
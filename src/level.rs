@@ -0,0 +1,700 @@
+use crate::wad::{LumpReader, WadError, WadFile};
+
+/// The sidedef index used by linedefs that have no sidedef on that side
+const NO_SIDEDEF: u16 = 0xFFFF;
+/// Set on a node child to indicate it refers to a subsector instead of another node
+const SUBSECTOR_FLAG: u16 = 0x8000;
+/// Terminates a linedef list in the blockmap
+const BLOCKMAP_LIST_END: u16 = 0xFFFF;
+
+/// A point on the map
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vertex {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// A thing (monster, item, player start, ...) placed on the map
+#[derive(Debug, Clone)]
+pub struct Thing {
+    pub x: i16,
+    pub y: i16,
+    /// The angle the thing faces, in degrees
+    pub angle: i16,
+    /// The doomednum of the thing
+    pub thing_type: i16,
+    pub flags: i16,
+}
+
+/// A line between two vertices
+#[derive(Debug, Clone)]
+pub struct LineDef {
+    /// The index of the start vertex
+    pub start_vertex: usize,
+    /// The index of the end vertex
+    pub end_vertex: usize,
+    pub flags: i16,
+    pub special: i16,
+    pub tag: i16,
+    /// The index of the right (front) sidedef
+    pub front_sidedef: Option<usize>,
+    /// The index of the left (back) sidedef, only set for two sided lines
+    pub back_sidedef: Option<usize>,
+}
+
+/// One side of a linedef, holding its textures and the sector it faces
+#[derive(Debug, Clone)]
+pub struct SideDef {
+    pub x_offset: i16,
+    pub y_offset: i16,
+    pub upper_texture: String,
+    pub lower_texture: String,
+    pub middle_texture: String,
+    /// The index of the sector this sidedef faces
+    pub sector: usize,
+}
+
+/// A segment of a linedef, as split by the node builder
+#[derive(Debug, Clone)]
+pub struct Seg {
+    /// The index of the start vertex
+    pub start_vertex: usize,
+    /// The index of the end vertex
+    pub end_vertex: usize,
+    /// The angle of the seg in binary angle units (a full circle is 65536)
+    pub angle: i16,
+    /// The index of the linedef this seg is part of
+    pub linedef: usize,
+    /// True if the seg runs along the back side of its linedef
+    pub back_side: bool,
+    /// The distance along the linedef to the start of the seg
+    pub offset: i16,
+}
+
+/// A convex part of a sector, made up of consecutive segs
+#[derive(Debug, Clone)]
+pub struct SubSector {
+    pub seg_count: usize,
+    /// The index of the first seg
+    pub first_seg: usize,
+}
+
+/// A bounding box in map coordinates
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingBox {
+    pub top: i16,
+    pub bottom: i16,
+    pub left: i16,
+    pub right: i16,
+}
+
+/// A child of a BSP node
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NodeChild {
+    /// The index of another node
+    Node(usize),
+    /// The index of a subsector, which is a leaf of the tree
+    SubSector(usize),
+}
+
+/// A node of the BSP tree
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// The start of the partition line
+    pub x: i16,
+    pub y: i16,
+    /// The direction of the partition line
+    pub dx: i16,
+    pub dy: i16,
+    pub right_bbox: BoundingBox,
+    pub left_bbox: BoundingBox,
+    pub right_child: NodeChild,
+    pub left_child: NodeChild,
+}
+
+/// An area of the map with a single floor and ceiling
+#[derive(Debug, Clone)]
+pub struct Sector {
+    pub floor_height: i16,
+    pub ceiling_height: i16,
+    pub floor_texture: String,
+    pub ceiling_texture: String,
+    pub light_level: i16,
+    pub special: i16,
+    pub tag: i16,
+}
+
+/// The sector to sector visibility table
+/// # Remarks
+/// A set bit means the sectors can never see each other, which lets the game skip line of sight
+/// checks.
+#[derive(Debug, Clone)]
+pub struct Reject {
+    sector_count: usize,
+    data: Vec<u8>,
+}
+
+/// The grid of 128x128 blocks used for collision detection
+#[derive(Debug, Clone)]
+pub struct BlockMap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: usize,
+    pub rows: usize,
+    /// The linedef indices crossing each block, row by row; like vanilla, these include linedef 0
+    /// from the delimiter at the start of each list
+    pub blocks: Vec<Vec<usize>>,
+}
+
+/// A fully loaded map
+pub struct Level {
+    /// The name of the map marker, e.g. `E1M1`
+    pub name: String,
+    pub things: Vec<Thing>,
+    pub linedefs: Vec<LineDef>,
+    pub sidedefs: Vec<SideDef>,
+    pub vertices: Vec<Vertex>,
+    pub segs: Vec<Seg>,
+    pub subsectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+    pub sectors: Vec<Sector>,
+    pub reject: Reject,
+    pub blockmap: BlockMap,
+}
+
+impl Level {
+    /// Loads a map from the WAD file
+    /// # Arguments
+    /// * `wad` - The WAD file to load the map from
+    /// * `name` - The name of the map marker, e.g. `E1M1`
+    /// # Returns
+    /// The level, or an error if a lump is missing, has the wrong size or references an entry
+    /// that does not exist
+    /// # Examples
+    /// ```no_run
+    /// use doom::level::Level;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let level = Level::load(&wad, "E1M1").unwrap();
+    /// let (start, end) = level.linedef_vertices(&level.linedefs[0]);
+    /// ```
+    pub fn load(wad: &WadFile, name: &str) -> Result<Self, WadError> {
+        let vertices = read_map_lump(wad, name, "VERTEXES", 4, |reader| {
+            Ok(Vertex {
+                x: reader.read_i16()?,
+                y: reader.read_i16()?,
+            })
+        })?;
+        let sectors = read_map_lump(wad, name, "SECTORS", 26, |reader| {
+            Ok(Sector {
+                floor_height: reader.read_i16()?,
+                ceiling_height: reader.read_i16()?,
+                floor_texture: reader.read_name()?,
+                ceiling_texture: reader.read_name()?,
+                light_level: reader.read_i16()?,
+                special: reader.read_i16()?,
+                tag: reader.read_i16()?,
+            })
+        })?;
+        let sidedefs = read_map_lump(wad, name, "SIDEDEFS", 30, |reader| {
+            Ok(SideDef {
+                x_offset: reader.read_i16()?,
+                y_offset: reader.read_i16()?,
+                upper_texture: reader.read_name()?,
+                lower_texture: reader.read_name()?,
+                middle_texture: reader.read_name()?,
+                sector: read_index(reader, sectors.len(), "sector")?,
+            })
+        })?;
+        let linedefs = read_map_lump(wad, name, "LINEDEFS", 14, |reader| {
+            Ok(LineDef {
+                start_vertex: read_index(reader, vertices.len(), "vertex")?,
+                end_vertex: read_index(reader, vertices.len(), "vertex")?,
+                flags: reader.read_i16()?,
+                special: reader.read_i16()?,
+                tag: reader.read_i16()?,
+                front_sidedef: read_sidedef_index(reader, sidedefs.len())?,
+                back_sidedef: read_sidedef_index(reader, sidedefs.len())?,
+            })
+        })?;
+        let segs = read_map_lump(wad, name, "SEGS", 12, |reader| {
+            Ok(Seg {
+                start_vertex: read_index(reader, vertices.len(), "vertex")?,
+                end_vertex: read_index(reader, vertices.len(), "vertex")?,
+                angle: reader.read_i16()?,
+                linedef: read_index(reader, linedefs.len(), "linedef")?,
+                back_side: reader.read_i16()? != 0,
+                offset: reader.read_i16()?,
+            })
+        })?;
+        let subsectors = read_map_lump(wad, name, "SSECTORS", 4, |reader| {
+            let seg_count = reader.read_u16()? as usize;
+            let first_seg = reader.read_u16()? as usize;
+            if first_seg + seg_count > segs.len() {
+                return Err(bad_reference(
+                    reader,
+                    format!(
+                        "subsector references segs {}..{} but there are only {}",
+                        first_seg,
+                        first_seg + seg_count,
+                        segs.len()
+                    ),
+                ));
+            }
+            Ok(SubSector {
+                seg_count,
+                first_seg,
+            })
+        })?;
+        let node_count = map_lump(wad, name, "NODES")?.1.len() / 28;
+        let nodes = read_map_lump(wad, name, "NODES", 28, |reader| {
+            Ok(Node {
+                x: reader.read_i16()?,
+                y: reader.read_i16()?,
+                dx: reader.read_i16()?,
+                dy: reader.read_i16()?,
+                right_bbox: read_bounding_box(reader)?,
+                left_bbox: read_bounding_box(reader)?,
+                right_child: read_node_child(reader, node_count, subsectors.len())?,
+                left_child: read_node_child(reader, node_count, subsectors.len())?,
+            })
+        })?;
+        let things = read_map_lump(wad, name, "THINGS", 10, |reader| {
+            Ok(Thing {
+                x: reader.read_i16()?,
+                y: reader.read_i16()?,
+                angle: reader.read_i16()?,
+                thing_type: reader.read_i16()?,
+                flags: reader.read_i16()?,
+            })
+        })?;
+        let reject = Reject::new(map_lump(wad, name, "REJECT")?.1, sectors.len());
+        let (blockmap_name, blockmap_data) = map_lump(wad, name, "BLOCKMAP")?;
+        let blockmap = decode_blockmap(&blockmap_name, blockmap_data, linedefs.len())?;
+
+        Ok(Self {
+            name: name.to_string(),
+            things,
+            linedefs,
+            sidedefs,
+            vertices,
+            segs,
+            subsectors,
+            nodes,
+            sectors,
+            reject,
+            blockmap,
+        })
+    }
+
+    /// Gets the start and end vertex of a linedef
+    pub fn linedef_vertices(&self, linedef: &LineDef) -> (&Vertex, &Vertex) {
+        (
+            &self.vertices[linedef.start_vertex],
+            &self.vertices[linedef.end_vertex],
+        )
+    }
+
+    /// Gets the sector a sidedef faces
+    pub fn sidedef_sector(&self, sidedef: &SideDef) -> &Sector {
+        &self.sectors[sidedef.sector]
+    }
+
+    /// Gets the sector in front of a linedef
+    pub fn front_sector(&self, linedef: &LineDef) -> Option<&Sector> {
+        linedef
+            .front_sidedef
+            .map(|sidedef| self.sidedef_sector(&self.sidedefs[sidedef]))
+    }
+
+    /// Gets the sector behind a linedef, only set for two sided lines
+    pub fn back_sector(&self, linedef: &LineDef) -> Option<&Sector> {
+        linedef
+            .back_sidedef
+            .map(|sidedef| self.sidedef_sector(&self.sidedefs[sidedef]))
+    }
+
+    /// Gets the root node of the BSP tree
+    /// # Remarks
+    /// Maps with a single subsector have no nodes at all, in which case the root is subsector 0.
+    pub fn root_node(&self) -> NodeChild {
+        match self.nodes.len() {
+            0 => NodeChild::SubSector(0),
+            count => NodeChild::Node(count - 1),
+        }
+    }
+}
+
+impl Reject {
+    /// Creates the reject table for the given amount of sectors
+    /// # Remarks
+    /// Some maps ship a REJECT lump that is too short; the missing part is treated as all zeroes,
+    /// meaning nothing is rejected.
+    fn new(data: &[u8], sector_count: usize) -> Self {
+        let mut data = data.to_vec();
+        data.resize((sector_count * sector_count).div_ceil(8), 0);
+        Self { sector_count, data }
+    }
+
+    /// Returns true if the sector `to` can never be seen from the sector `from`
+    pub fn is_rejected(&self, from: usize, to: usize) -> bool {
+        let bit = from * self.sector_count + to;
+        self.data[bit / 8] & (1 << (bit % 8)) != 0
+    }
+}
+
+impl BlockMap {
+    /// Gets the linedefs crossing the block at the given column and row
+    pub fn block_lines(&self, column: usize, row: usize) -> &[usize] {
+        if column >= self.columns || row >= self.rows {
+            return &[];
+        }
+        &self.blocks[row * self.columns + column]
+    }
+}
+
+/// Gets the map lump with the given name, along with the name used to report errors
+fn map_lump<'a>(wad: &'a WadFile, map: &str, name: &str) -> Result<(String, &'a [u8]), WadError> {
+    let lump_name = format!("{}/{}", map, name);
+    match wad.get_map_lump(map, name) {
+        Some(lump) => Ok((lump_name, lump.data()?)),
+        None => Err(WadError::MissingLump { name: lump_name }),
+    }
+}
+
+/// Reads a map lump consisting of fixed size records
+/// # Arguments
+/// * `wad` - The WAD file to read from
+/// * `map` - The name of the map marker
+/// * `name` - The name of the map lump
+/// * `record_size` - The size of a single record in bytes
+/// * `decode` - Decodes a single record from the reader
+fn read_map_lump<T>(
+    wad: &WadFile,
+    map: &str,
+    name: &str,
+    record_size: usize,
+    decode: impl Fn(&mut LumpReader) -> Result<T, WadError>,
+) -> Result<Vec<T>, WadError> {
+    let (lump_name, data) = map_lump(wad, map, name)?;
+    if data.len() % record_size != 0 {
+        return Err(WadError::TruncatedLump {
+            name: lump_name,
+            offset: data.len() - data.len() % record_size,
+        });
+    }
+
+    let mut reader = LumpReader::new(&lump_name, data);
+    (0..data.len() / record_size)
+        .map(|_| decode(&mut reader))
+        .collect()
+}
+
+/// Reads an index and checks it refers to one of `count` entries
+fn read_index(reader: &mut LumpReader, count: usize, kind: &str) -> Result<usize, WadError> {
+    let index = reader.read_u16()?;
+    check_index(reader, index, count, kind)
+}
+
+fn read_sidedef_index(reader: &mut LumpReader, count: usize) -> Result<Option<usize>, WadError> {
+    let index = reader.read_u16()?;
+    if index == NO_SIDEDEF {
+        return Ok(None);
+    }
+    check_index(reader, index, count, "sidedef").map(Some)
+}
+
+fn read_bounding_box(reader: &mut LumpReader) -> Result<BoundingBox, WadError> {
+    Ok(BoundingBox {
+        top: reader.read_i16()?,
+        bottom: reader.read_i16()?,
+        left: reader.read_i16()?,
+        right: reader.read_i16()?,
+    })
+}
+
+fn read_node_child(
+    reader: &mut LumpReader,
+    node_count: usize,
+    subsector_count: usize,
+) -> Result<NodeChild, WadError> {
+    let child = reader.read_u16()?;
+    match child & SUBSECTOR_FLAG {
+        0 => Ok(NodeChild::Node(check_index(
+            reader, child, node_count, "node",
+        )?)),
+        _ => Ok(NodeChild::SubSector(check_index(
+            reader,
+            child & !SUBSECTOR_FLAG,
+            subsector_count,
+            "subsector",
+        )?)),
+    }
+}
+
+/// Checks an index that was just read refers to one of `count` entries
+fn check_index(
+    reader: &LumpReader,
+    index: u16,
+    count: usize,
+    kind: &str,
+) -> Result<usize, WadError> {
+    if index as usize >= count {
+        return Err(bad_reference(
+            reader,
+            format!("{} {} referenced but there are only {}", kind, index, count),
+        ));
+    }
+    Ok(index as usize)
+}
+
+/// Decodes the BLOCKMAP lump
+/// # Remarks
+/// Every block list starts with a 0 delimiter and ends with 0xFFFF. Like vanilla's
+/// P_BlockLinesIterator, the delimiter is read as linedef 0, so every list holds linedef 0 even
+/// when the line doesn't cross the block. A list can't tell a delimiter from linedef 0, so this
+/// also reads the lists of node builders that leave the delimiter out correctly.
+fn decode_blockmap(name: &str, data: &[u8], linedef_count: usize) -> Result<BlockMap, WadError> {
+    let mut reader = LumpReader::new(name, data);
+    let origin_x = reader.read_i16()?;
+    let origin_y = reader.read_i16()?;
+    let columns = reader.read_u16()? as usize;
+    let rows = reader.read_u16()? as usize;
+
+    let offsets = (0..columns * rows)
+        .map(|_| reader.read_u16())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let blocks = offsets
+        .iter()
+        .map(|&offset| {
+            // Offsets are in 16 bit words from the start of the lump
+            reader.set_position(offset as usize * 2);
+            let mut lines = Vec::new();
+            loop {
+                let line = reader.read_u16()?;
+                if line == BLOCKMAP_LIST_END {
+                    return Ok(lines);
+                }
+                lines.push(check_index(&reader, line, linedef_count, "linedef")?);
+            }
+        })
+        .collect::<Result<Vec<_>, WadError>>()?;
+
+    Ok(BlockMap {
+        origin_x,
+        origin_y,
+        columns,
+        rows,
+        blocks,
+    })
+}
+
+fn bad_reference(reader: &LumpReader, reason: String) -> WadError {
+    WadError::BadReference {
+        name: reader.name().to_string(),
+        offset: reader.position(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::{WadKind, WadWriter};
+
+    /// Encodes little endian 16 bit values
+    fn words(values: &[i32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|&value| (value as u16).to_le_bytes())
+            .collect()
+    }
+
+    /// Encodes a texture or flat name, padded to 8 bytes
+    fn name(name: &str) -> Vec<u8> {
+        let mut data = name.as_bytes().to_vec();
+        data.resize(8, 0);
+        data
+    }
+
+    fn sidedef(sector: i32) -> Vec<u8> {
+        [
+            words(&[0, 0]),
+            name("-"),
+            name("-"),
+            name("STARTAN3"),
+            words(&[sector]),
+        ]
+        .concat()
+    }
+
+    fn sector(light_level: i32) -> Vec<u8> {
+        [
+            words(&[0, 128]),
+            name("FLOOR4_8"),
+            name("CEIL3_5"),
+            words(&[light_level, 0, 0]),
+        ]
+        .concat()
+    }
+
+    /// The lumps of a square room split into two sectors
+    fn map_lumps() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            ("THINGS", words(&[32, 32, 90, 1, 7, 16, 48, 0, 2001, 7])),
+            (
+                "LINEDEFS",
+                words(&[
+                    0, 1, 1, 0, 0, 0, 0xFFFF, //
+                    1, 2, 1, 0, 0, 1, 0xFFFF, //
+                    2, 3, 1, 0, 0, 2, 0xFFFF, //
+                    3, 0, 4, 11, 5, 3, 0, //
+                ]),
+            ),
+            (
+                "SIDEDEFS",
+                [sidedef(0), sidedef(0), sidedef(1), sidedef(1)].concat(),
+            ),
+            ("VERTEXES", words(&[0, 0, 64, 0, 64, 64, 0, 64])),
+            (
+                "SEGS",
+                words(&[
+                    0, 1, 0, 0, 0, 0, //
+                    1, 2, 16384, 1, 0, 0, //
+                    2, 3, -32768, 2, 0, 0, //
+                    3, 0, -16384, 3, 0, 0, //
+                ]),
+            ),
+            ("SSECTORS", words(&[2, 0, 2, 2])),
+            (
+                "NODES",
+                words(&[0, 32, 64, 0, 64, 32, 0, 64, 32, 0, 0, 64, 0x8000, 0x8001]),
+            ),
+            ("SECTORS", [sector(160), sector(255)].concat()),
+            // Sector 0 and 1 can't see each other
+            ("REJECT", vec![0b0110]),
+            (
+                "BLOCKMAP",
+                // The second list leaves out the leading delimiter
+                words(&[-8, -8, 2, 1, 6, 10, 0, 1, 2, 0xFFFF, 3, 0xFFFF]),
+            ),
+        ]
+    }
+
+    /// Writes the map lumps as `E1M1` to a temporary WAD file and loads the level
+    fn load(test: &str, lumps: Vec<(&str, Vec<u8>)>) -> Result<Level, WadError> {
+        let mut writer = WadWriter::new(WadKind::Pwad);
+        writer.add_map("E1M1", lumps)?;
+        let path = std::env::temp_dir().join(format!("level_{}_{}.wad", test, std::process::id()));
+        writer.write(&path)?;
+        let wad = WadFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        Level::load(&wad?, "E1M1")
+    }
+
+    /// Replaces one of the map lumps
+    fn with_lump(name: &'static str, data: Vec<u8>) -> Vec<(&'static str, Vec<u8>)> {
+        let mut lumps = map_lumps();
+        lumps.iter_mut().find(|lump| lump.0 == name).unwrap().1 = data;
+        lumps
+    }
+
+    #[test]
+    fn maps_round_trip() {
+        let level = load("round_trip", map_lumps()).unwrap();
+        assert_eq!(level.name, "E1M1");
+
+        assert_eq!(level.things.len(), 2);
+        let thing = &level.things[1];
+        assert_eq!(
+            (thing.x, thing.y, thing.angle, thing.thing_type, thing.flags),
+            (16, 48, 0, 2001, 7)
+        );
+
+        assert_eq!(level.linedefs.len(), 4);
+        let (start, end) = level.linedef_vertices(&level.linedefs[1]);
+        assert_eq!(
+            (*start, *end),
+            (Vertex { x: 64, y: 0 }, Vertex { x: 64, y: 64 })
+        );
+        assert_eq!(level.linedefs[0].back_sidedef, None);
+        let linedef = &level.linedefs[3];
+        assert_eq!((linedef.flags, linedef.special, linedef.tag), (4, 11, 5));
+        assert_eq!(
+            (linedef.front_sidedef, linedef.back_sidedef),
+            (Some(3), Some(0))
+        );
+        assert_eq!(level.front_sector(linedef).unwrap().light_level, 255);
+        assert_eq!(level.back_sector(linedef).unwrap().light_level, 160);
+
+        assert_eq!(level.sidedefs[2].middle_texture, "STARTAN3");
+        assert_eq!(level.sidedefs[2].upper_texture, "-");
+        let sector = &level.sectors[0];
+        assert_eq!((sector.floor_height, sector.ceiling_height), (0, 128));
+        assert_eq!(sector.floor_texture, "FLOOR4_8");
+        assert_eq!(sector.ceiling_texture, "CEIL3_5");
+
+        assert_eq!(level.segs[2].angle, -32768);
+        assert_eq!(level.subsectors[1].first_seg, 2);
+        assert_eq!(level.root_node(), NodeChild::Node(0));
+        assert_eq!(level.nodes[0].left_child, NodeChild::SubSector(1));
+        assert_eq!(level.nodes[0].right_bbox.bottom, 32);
+
+        assert!(level.reject.is_rejected(0, 1));
+        assert!(level.reject.is_rejected(1, 0));
+        assert!(!level.reject.is_rejected(1, 1));
+    }
+
+    #[test]
+    fn blockmap_lists_keep_the_delimiter_as_linedef_0() {
+        let blockmap = load("blockmap", map_lumps()).unwrap().blockmap;
+        assert_eq!((blockmap.origin_x, blockmap.origin_y), (-8, -8));
+        assert_eq!((blockmap.columns, blockmap.rows), (2, 1));
+        assert_eq!(blockmap.block_lines(0, 0), &[0, 1, 2]);
+        assert_eq!(blockmap.block_lines(1, 0), &[3]);
+        assert_eq!(blockmap.block_lines(2, 0), &[] as &[usize]);
+    }
+
+    #[test]
+    fn short_reject_tables_reject_nothing() {
+        let level = load("short_reject", with_lump("REJECT", Vec::new())).unwrap();
+        assert!(!level.reject.is_rejected(0, 1));
+    }
+
+    #[test]
+    fn missing_entries_are_bad_references() {
+        let linedefs = words(&[0, 9, 1, 0, 0, 0, 0xFFFF]);
+        assert!(matches!(
+            load("bad_vertex", with_lump("LINEDEFS", linedefs)),
+            Err(WadError::BadReference { name, .. }) if name == "E1M1/LINEDEFS"
+        ));
+        let blockmap = words(&[0, 0, 1, 1, 5, 4, 0xFFFF]);
+        assert!(matches!(
+            load("bad_blockmap", with_lump("BLOCKMAP", blockmap)),
+            Err(WadError::BadReference { name, .. }) if name == "E1M1/BLOCKMAP"
+        ));
+    }
+
+    #[test]
+    fn partial_records_are_truncated() {
+        assert!(matches!(
+            load("truncated", with_lump("THINGS", vec![0; 15])),
+            Err(WadError::TruncatedLump { name, offset: 10 }) if name == "E1M1/THINGS"
+        ));
+    }
+
+    #[test]
+    fn unknown_maps_are_missing() {
+        let mut writer = WadWriter::new(WadKind::Pwad);
+        writer.add_map("E1M1", map_lumps()).unwrap();
+        let path = std::env::temp_dir().join(format!("level_missing_{}.wad", std::process::id()));
+        writer.write(&path).unwrap();
+        let wad = WadFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            Level::load(&wad.unwrap(), "E1M2"),
+            Err(WadError::MissingLump { .. })
+        ));
+    }
+}
@@ -0,0 +1,26 @@
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 200;
+
+pub mod args;
+pub mod audio;
+pub mod audio_backend;
+pub mod cache;
+pub mod flat;
+pub mod game;
+pub mod game_mode;
+pub mod genmidi;
+pub mod image_tools;
+pub mod level;
+pub mod lighting;
+pub mod menu;
+pub mod mixer;
+pub mod music;
+pub mod opl;
+pub mod opl_music;
+pub mod pc_speaker;
+pub mod sounds;
+pub mod sprite;
+pub mod texture;
+pub mod video;
+pub mod wad;
+pub mod wad_search;
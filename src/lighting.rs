@@ -0,0 +1,153 @@
+use crate::wad::{Colormap, WadError, WadFile};
+use crate::WIDTH;
+
+/// The amount of light levels sectors are divided into
+pub const LIGHT_LEVELS: usize = 16;
+/// The shift from a sector's 0-255 light level to one of the light levels
+pub const LIGHT_SEG_SHIFT: u32 = 4;
+/// The amount of distances in the table for floors and ceilings
+pub const MAX_LIGHT_Z: usize = 128;
+/// The shift from a fixed point distance to an index into the table for floors and ceilings
+pub const LIGHT_Z_SHIFT: u32 = 20;
+/// The amount of scales in the table for walls and sprites
+pub const MAX_LIGHT_SCALE: usize = 48;
+/// The shift from a fixed point scale to an index into the table for walls and sprites
+pub const LIGHT_SCALE_SHIFT: u32 = 12;
+/// The amount of colormaps that darken the palette, from full bright to black
+pub const NUM_COLORMAPS: usize = 32;
+/// The colormap that inverts the palette to grayscale, used by the invulnerability sphere
+pub const INVERSE_COLORMAP: usize = 32;
+/// How fast the light diminishes with distance
+const DIST_MAP: i64 = 2;
+const FRAC_BITS: u32 = 16;
+
+/// The COLORMAP tables along with the light tables that pick one of them
+/// # Remarks
+/// This works like vanilla's zlight and scalelight tables: the sector's light level picks a row
+/// and the distance (floors and ceilings) or scale (walls and sprites) picks the colormap in that
+/// row, so things get darker the further away they are. The tables assume the full 320 pixel
+/// wide view at high detail.
+pub struct Lighting {
+    colormaps: Vec<Colormap>,
+    /// The colormap for floors and ceilings by light level and distance
+    zlight: [[usize; MAX_LIGHT_Z]; LIGHT_LEVELS],
+    /// The colormap for walls and sprites by light level and scale
+    scalelight: [[usize; MAX_LIGHT_SCALE]; LIGHT_LEVELS],
+}
+
+impl Lighting {
+    /// Loads COLORMAP and builds the light tables
+    /// # Examples
+    /// ```no_run
+    /// use doom::lighting::Lighting;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let lighting = Lighting::load(&wad).unwrap();
+    /// // A wall in a sector with light level 160, 8 scale units away
+    /// let colormap = lighting.colormap(lighting.wall_colormap(160, 0, 1 << 15));
+    /// ```
+    pub fn load(wad: &WadFile) -> Result<Self, WadError> {
+        Ok(Self::new(wad.get_colormaps()?))
+    }
+
+    /// Builds the light tables for a set of colormaps
+    pub fn new(colormaps: Vec<Colormap>) -> Self {
+        let mut zlight = [[0; MAX_LIGHT_Z]; LIGHT_LEVELS];
+        let mut scalelight = [[0; MAX_LIGHT_SCALE]; LIGHT_LEVELS];
+
+        for level in 0..LIGHT_LEVELS {
+            let start_map = ((LIGHT_LEVELS - 1 - level) * 2 * NUM_COLORMAPS / LIGHT_LEVELS) as i64;
+
+            for (z, colormap) in zlight[level].iter_mut().enumerate() {
+                // The scale of something at this distance, like R_InitLightTables
+                let scale = (((WIDTH as i64 / 2) << (FRAC_BITS * 2))
+                    / ((z as i64 + 1) << LIGHT_Z_SHIFT))
+                    >> LIGHT_SCALE_SHIFT;
+                *colormap = clamp_colormap(start_map - scale / DIST_MAP);
+            }
+
+            for (scale, colormap) in scalelight[level].iter_mut().enumerate() {
+                *colormap = clamp_colormap(start_map - scale as i64 / DIST_MAP);
+            }
+        }
+
+        Self {
+            colormaps,
+            zlight,
+            scalelight,
+        }
+    }
+
+    /// Gets a colormap by index
+    /// # Remarks
+    /// Index 0 is full bright, 31 is nearly black and `INVERSE_COLORMAP` is the invulnerability map.
+    pub fn colormap(&self, index: usize) -> &Colormap {
+        &self.colormaps[index]
+    }
+
+    /// Gets the colormap index for a wall column or sprite
+    /// # Arguments
+    /// * `light_level` - The light level of the sector, 0 to 255
+    /// * `extra_light` - Extra light levels, e.g. from the muzzle flash or vanilla's fake contrast
+    /// * `scale` - The fixed point scale the column is drawn at
+    pub fn wall_colormap(&self, light_level: i16, extra_light: isize, scale: i32) -> usize {
+        let index = ((scale.max(0) >> LIGHT_SCALE_SHIFT) as usize).min(MAX_LIGHT_SCALE - 1);
+        self.scalelight[light_index(light_level, extra_light)][index]
+    }
+
+    /// Gets the colormap index for a span of a floor or ceiling
+    /// # Arguments
+    /// * `light_level` - The light level of the sector, 0 to 255
+    /// * `extra_light` - Extra light levels, e.g. from the muzzle flash
+    /// * `distance` - The fixed point distance from the view to the span
+    pub fn plane_colormap(&self, light_level: i16, extra_light: isize, distance: i32) -> usize {
+        let index = ((distance.max(0) >> LIGHT_Z_SHIFT) as usize).min(MAX_LIGHT_Z - 1);
+        self.zlight[light_index(light_level, extra_light)][index]
+    }
+}
+
+/// Gets the row of the light tables for a sector's light level
+fn light_index(light_level: i16, extra_light: isize) -> usize {
+    let index = (light_level.max(0) >> LIGHT_SEG_SHIFT) as isize + extra_light;
+    index.clamp(0, LIGHT_LEVELS as isize - 1) as usize
+}
+
+fn clamp_colormap(level: i64) -> usize {
+    level.clamp(0, NUM_COLORMAPS as i64 - 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The expected values are the ones vanilla's R_InitLightTables and R_ExecuteSetViewSize
+    // give for a full screen view at high detail
+
+    #[test]
+    fn zlight_matches_vanilla() {
+        let lighting = Lighting::new(Vec::new());
+        assert_eq!(lighting.zlight[0][0], 0);
+        assert_eq!(lighting.zlight[0][127], 31);
+        assert_eq!(lighting.zlight[8][9], 20);
+        assert_eq!(lighting.zlight[8][127], 28);
+        assert_eq!(lighting.zlight[15][127], 0);
+    }
+
+    #[test]
+    fn scalelight_matches_vanilla() {
+        let lighting = Lighting::new(Vec::new());
+        assert_eq!(lighting.scalelight[0][0], 31);
+        assert_eq!(lighting.scalelight[8][10], 23);
+        assert_eq!(lighting.scalelight[8][47], 5);
+        assert_eq!(lighting.scalelight[15][0], 0);
+    }
+
+    #[test]
+    fn light_levels_pick_the_row() {
+        let lighting = Lighting::new(Vec::new());
+        // Light level 136 is row 8, and the fake contrast of a wall moves it a row up
+        assert_eq!(lighting.wall_colormap(136, 0, 10 << LIGHT_SCALE_SHIFT), 23);
+        assert_eq!(lighting.wall_colormap(136, 1, 10 << LIGHT_SCALE_SHIFT), 19);
+        assert_eq!(lighting.plane_colormap(136, 0, 9 << LIGHT_Z_SHIFT), 20);
+    }
+}
@@ -1,19 +1,38 @@
-use menu::Menu;
+use doom::args::Args;
+use doom::audio::SfxDevice;
+use doom::game::{self, Game, GameState};
+use doom::game_mode::GameMode;
+use doom::menu::Menu;
+use doom::video::Screen;
+use doom::wad_search::find_iwad;
+use doom::{wad, HEIGHT, WIDTH};
 use minifb::{Key, Window, WindowOptions};
-
-pub const WIDTH: usize = 320;
-pub const HEIGHT: usize = 200;
-const WAD_FILE: &str = "doom1.wad";
-
-mod game;
-use game::{Game, GameState};
-mod audio;
-mod image_tools;
-mod menu;
-mod wad;
+use std::time::Instant;
 
 /// The main function
 fn main() {
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let iwad = find_iwad(args.iwad.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut wad = wad::WadFile::load(&iwad).unwrap_or_else(|e| {
+        panic!("Unable to load WAD file: {}", e);
+    });
+    // The game mode is detected before PWADs can add maps of other games
+    let mode = GameMode::detect(&wad).unwrap_or_else(|| {
+        eprintln!("{} is not an IWAD: it has no maps", iwad.display());
+        std::process::exit(1);
+    });
+    for pwad in &args.files {
+        wad.add_file(pwad).unwrap_or_else(|e| {
+            panic!("Unable to load PWAD {}: {}", pwad.display(), e);
+        });
+    }
+
     let mut window = Window::new(
         "Doom",
         WIDTH,
@@ -33,12 +52,25 @@ fn main() {
         panic!("Unable to start new window: {}", e);
     });
 
-    let wad = wad::WadFile::load(WAD_FILE);
-    let mut game = game::Game::new(wad);
+    let palettes = wad.get_palettes().unwrap_or_else(|e| {
+        panic!("Unable to load palettes: {}", e);
+    });
+    let mut screen = Screen::new(palettes);
+    let mut game = game::Game::new(wad, mode);
+    if args.pc_speaker {
+        game.sfx_device = SfxDevice::PcSpeaker;
+    }
     let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    let mut last_frame = Instant::now();
     while window.is_open() && game.state != GameState::Quit {
+        let now = Instant::now();
+        if let Err(e) = game.sound.update(now - last_frame) {
+            eprintln!("Unable to mix sounds: {}", e);
+        }
+        last_frame = now;
         update_game_state(&mut game, &mut window);
-        render_game_state(&mut buffer, &game);
+        render_game_state(&mut screen, &game);
+        screen.present(&mut buffer);
         window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
     }
 }
@@ -63,13 +95,13 @@ fn update_game_state(game: &mut Game, window: &mut Window) {
     }
 }
 
-/// Renders the game state to the screen buffer
-fn render_game_state(buffer: &mut [u32], game: &Game) {
-    // Clear the buffer
-    buffer.iter_mut().for_each(|pixel| *pixel = 0);
+/// Renders the game state to the screen
+fn render_game_state(screen: &mut Screen, game: &Game) {
+    // Clear the screen
+    screen.clear(0);
     match game.state {
         GameState::Menu => {
-            game.menu.render(buffer, game);
+            game.menu.render(screen, game);
         }
         GameState::Playing => {
             // Render gameplay graphics
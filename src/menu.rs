@@ -1,9 +1,10 @@
 use crate::game::{Game, GameState, Skill};
-use crate::image_tools::render_image;
-use crate::wad::WadFile;
+use crate::sounds::SfxId;
+use crate::video::Screen;
+use crate::wad::{Picture, WadFile};
 use crate::HEIGHT;
-use image::DynamicImage;
 use minifb::{Key, Window};
+use std::rc::Rc;
 const LINE_HEIGHT: usize = 15;
 const PADDING: usize = 3;
 
@@ -23,12 +24,14 @@ const SELECT_HURT_ME_LUMP_NAME: &str = "M_HURT";
 const SELECT_ULTRA_VIOLENT_LUMP_NAME: &str = "M_ULTRA";
 const SELECT_NIGHTMARE_LUMP_NAME: &str = "M_NMARE";
 
-const SHOTGUN_LUMP_NAME: &str = "DSPISTOL";
-
+// the titles of the load and save menus, which are not implemented yet
+#[allow(dead_code)]
 const SELECT_LOAD_LUMP_NAME: &str = "M_LOADG";
+#[allow(dead_code)]
 const SELECT_SAVE_LUMP_NAME: &str = "M_SAVEG";
 
-// the option menu items
+// the option menu items; the messages toggle is not implemented yet
+#[allow(dead_code)]
 const OPT_MESSAGES_LUMP_NAME: &str = "M_MESSG";
 const OPT_DETAIL_LUMP_NAME: &str = "M_DETAIL";
 const OPT_SCREEN_SIZE_LUMP_NAME: &str = "M_SCRNSZ";
@@ -40,7 +43,7 @@ const INPUT_COOLDOWN: std::time::Duration = std::time::Duration::from_millis(200
 /// Defines the menu options for a menu
 struct MenuItem {
     /// The image resource from the WAD file to display for this menu item
-    image: DynamicImage,
+    image: Rc<Picture>,
     /// The action to take when this menu item is selected
     action: fn(&mut Game),
 }
@@ -49,7 +52,9 @@ enum MenuType {
     Root,
     Episode,
     Skill,
+    #[allow(dead_code)] // Not implemented yet
     Load,
+    #[allow(dead_code)] // Not implemented yet
     Save,
     Options,
 }
@@ -59,13 +64,13 @@ pub struct Menu {
     menu_type: MenuType,
     options: Vec<MenuItem>,
     selected: usize,
-    title: DynamicImage,
+    title: Rc<Picture>,
     last_input: std::time::Instant,
 }
 
 impl MenuItem {
     /// Creates a new menu item
-    pub fn new(image: DynamicImage, action: fn(&mut Game)) -> Self {
+    pub fn new(image: Rc<Picture>, action: fn(&mut Game)) -> Self {
         Self { image, action }
     }
 }
@@ -75,19 +80,19 @@ impl Menu {
     pub fn root(wad: &WadFile) -> Self {
         Self {
             menu_type: MenuType::Root,
-            options: get_root_menu(&wad),
+            options: get_root_menu(wad),
             title: wad.get_image(TITLE_LUMP_NAME).unwrap(),
             selected: 0,
             last_input: std::time::Instant::now(),
         }
     }
 
-    pub fn set_menu_type(&mut self, menu_type: MenuType) -> &mut Self {
+    fn set_menu_type(&mut self, menu_type: MenuType) -> &mut Self {
         self.menu_type = menu_type;
         self
     }
 
-    pub fn set_options(&mut self, options: Vec<MenuItem>) -> &mut Self {
+    fn set_options(&mut self, options: Vec<MenuItem>) -> &mut Self {
         self.options = options;
         self
     }
@@ -97,7 +102,7 @@ impl Menu {
         self
     }
 
-    pub fn set_title(&mut self, title: DynamicImage) -> &mut Self {
+    pub fn set_title(&mut self, title: Rc<Picture>) -> &mut Self {
         self.title = title;
         self
     }
@@ -125,11 +130,7 @@ impl Menu {
             game.menu.set_selected(new_selected);
         }
         if window.is_key_down(Key::Enter) {
-            let shot_gun_lump = game
-                .wad
-                .get_lump(SHOTGUN_LUMP_NAME)
-                .expect("Shotgun lump not found");
-            crate::audio::play_sound((&shot_gun_lump).to_vec());
+            game.start_sound(SfxId::Pistol, None);
             let action = game.menu.options[game.menu.selected].action;
             action(game);
         }
@@ -137,6 +138,7 @@ impl Menu {
             match game.menu.menu_type {
                 MenuType::Root => game.set_state(GameState::Playing),
                 MenuType::Episode => set_root_menu(game),
+                MenuType::Skill if game.mode.episode_count() == 0 => set_root_menu(game),
                 MenuType::Skill => set_episode_menu(game),
                 MenuType::Load => set_root_menu(game),
                 MenuType::Save => set_root_menu(game),
@@ -145,35 +147,34 @@ impl Menu {
         }
     }
 
-    /// Renders the menu to the buffer
+    /// Renders the menu to the screen
     /// # Arguments
-    /// * `buffer` - The buffer to render to
+    /// * `screen` - The screen to render to
     /// * `game` - The game to render
-    pub fn render(&self, buffer: &mut [u32], game: &Game) {
-        // Copy the image's pixels into the buffer
-        render_image(&game.background, 0, 0, buffer);
+    pub fn render(&self, screen: &mut Screen, game: &Game) {
+        screen.draw_picture(&game.background, 0, 0);
 
         // Render the logo
-        let start_y = (HEIGHT - self.title.height() as usize) / 4;
-        let x = crate::WIDTH / 2 - self.title.width() as usize / 2;
-        render_image(&self.title, x, start_y, buffer);
+        let start_y = (HEIGHT - self.title.height) / 4;
+        let x = crate::WIDTH / 2 - self.title.width / 2;
+        screen.draw_picture(&self.title, x as isize, start_y as isize);
 
-        let mut start_y = start_y + self.title.height() as usize + PADDING;
+        let mut start_y = start_y + self.title.height + PADDING;
         let max_width = self
             .options
             .iter()
-            .map(|option| option.image.width())
+            .map(|option| option.image.width)
             .max()
             .unwrap_or(100);
-        let x = crate::WIDTH / 2 - max_width as usize / 2;
+        let x = crate::WIDTH / 2 - max_width / 2;
         for (i, option) in self.options.iter().enumerate() {
             let y = start_y;
-            let skull_x = x - game.skull.width() as usize - PADDING;
+            let skull_x = x - game.skull.width - PADDING;
             // render the skull if this is the selected option
             if i == self.selected {
-                render_image(&game.skull, skull_x, y, buffer);
+                screen.draw_picture(&game.skull, skull_x as isize, y as isize);
             }
-            render_image(&option.image, x, y, buffer);
+            screen.draw_picture(&option.image, x as isize, y as isize);
             start_y += LINE_HEIGHT;
         }
     }
@@ -208,37 +209,32 @@ fn get_root_menu(wad: &WadFile) -> Vec<MenuItem> {
     ]
 }
 
+/// Shows the episode menu, or the skill menu right away for games without episodes
 fn set_episode_menu(game: &mut Game) {
-    game.menu
-        .set_options(vec![
-            MenuItem::new(
-                game.wad
-                    .get_image(format!("{}{}", SELECT_EPISODE_PREFIX, 1).as_str())
-                    .unwrap(),
-                |game| {
-                    set_skill_menu(game);
-                    game.set_episode(1);
-                },
-            ),
-            MenuItem::new(
-                game.wad
-                    .get_image(format!("{}{}", SELECT_EPISODE_PREFIX, 2).as_str())
-                    .unwrap(),
-                |game| {
-                    set_skill_menu(game);
-                    game.set_episode(2);
-                },
-            ),
+    let episode_count = game.mode.episode_count();
+    if episode_count == 0 {
+        game.set_episode(1);
+        set_skill_menu(game);
+        return;
+    }
+
+    let options = (1..=episode_count)
+        .map(|episode| {
             MenuItem::new(
                 game.wad
-                    .get_image(format!("{}{}", SELECT_EPISODE_PREFIX, 3).as_str())
+                    .get_image(format!("{}{}", SELECT_EPISODE_PREFIX, episode).as_str())
                     .unwrap(),
                 |game| {
+                    // The menu items are in episode order
+                    game.set_episode(game.menu.selected + 1);
                     set_skill_menu(game);
-                    game.set_episode(2);
                 },
-            ),
-        ])
+            )
+        })
+        .collect();
+
+    game.menu
+        .set_options(options)
         .set_selected(0)
         .set_menu_type(MenuType::Episode)
         .set_title(game.wad.get_image(SELECT_EPISODE_LUMP_NAME).unwrap());
@@ -280,8 +276,7 @@ fn set_skill_menu(game: &mut Game) {
             MenuItem::new(
                 game.wad.get_image(SELECT_TOO_YOUNG_LUMP_NAME).unwrap(),
                 |game| {
-                    game.set_skill(Skill::TooYoungToDie);
-                    game.set_state(GameState::Playing);
+                    start_game(game, Skill::TooYoungToDie);
                 },
             ),
             MenuItem::new(
@@ -289,29 +284,25 @@ fn set_skill_menu(game: &mut Game) {
                     .get_image(SELECT_HEY_NOT_TOO_ROUGH_LUMP_NAME)
                     .unwrap(),
                 |game| {
-                    game.set_skill(Skill::HeyNotTooRough);
-                    game.set_state(GameState::Playing);
+                    start_game(game, Skill::HeyNotTooRough);
                 },
             ),
             MenuItem::new(
                 game.wad.get_image(SELECT_HURT_ME_LUMP_NAME).unwrap(),
                 |game| {
-                    game.set_skill(Skill::HurtMePlenty);
-                    game.set_state(GameState::Playing);
+                    start_game(game, Skill::HurtMePlenty);
                 },
             ),
             MenuItem::new(
                 game.wad.get_image(SELECT_ULTRA_VIOLENT_LUMP_NAME).unwrap(),
                 |game| {
-                    game.set_skill(Skill::UltraViolence);
-                    game.set_state(GameState::Playing);
+                    start_game(game, Skill::UltraViolence);
                 },
             ),
             MenuItem::new(
                 game.wad.get_image(SELECT_NIGHTMARE_LUMP_NAME).unwrap(),
                 |game| {
-                    game.set_skill(Skill::Nightmare);
-                    game.set_state(GameState::Playing);
+                    start_game(game, Skill::Nightmare);
                 },
             ),
         ])
@@ -319,3 +310,13 @@ fn set_skill_menu(game: &mut Game) {
         .set_menu_type(MenuType::Skill)
         .set_title(game.wad.get_image(SELECT_SKILL_LUMP_NAME).unwrap());
 }
+
+/// Starts a new game at the selected skill, loading the first map of the selected episode
+fn start_game(game: &mut Game, skill: Skill) {
+    game.set_skill(skill);
+    game.map = 1;
+    match game.load_level() {
+        Ok(()) => game.set_state(GameState::Playing),
+        Err(e) => eprintln!("Unable to load level: {}", e),
+    }
+}
@@ -0,0 +1,331 @@
+use crate::audio::Sound;
+use crate::opl_music::MusicPlayer;
+use std::sync::Arc;
+
+/// The amount of sounds that can play at the same time, vanilla's default `snd_channels`
+pub const NUM_CHANNELS: usize = 8;
+/// The highest volume of a single channel
+pub const MAX_CHANNEL_VOLUME: i32 = 127;
+/// The highest master sound effect volume, as set in the sound menu
+pub const MAX_SFX_VOLUME: i32 = 15;
+/// The highest music volume, as set in the sound menu
+pub const MAX_MUSIC_VOLUME: i32 = 15;
+/// The stereo separation of a sound straight ahead; 0 is fully left and 254 fully right
+pub const NORM_SEPARATION: i32 = 128;
+
+/// What makes a sound, like a monster or a door
+/// # Remarks
+/// Vanilla uses the address of the map object. Sounds without an origin, like the menu sounds,
+/// never replace each other.
+pub type Origin = usize;
+
+/// A sound playing on a channel
+pub struct Channel {
+    pub sound: Arc<Sound>,
+    pub origin: Option<Origin>,
+    /// The priority of the sound, a lower number is more important
+    pub priority: i32,
+    /// The volume, 0 to `MAX_CHANNEL_VOLUME`
+    pub volume: i32,
+    /// The volume the sound was started with, before it was attenuated by distance
+    pub base_volume: i32,
+    /// The stereo separation, see `NORM_SEPARATION`
+    pub separation: i32,
+    /// The position in the samples of the sound, in samples
+    position: f64,
+}
+
+/// Mixes the sounds playing on a fixed number of channels into a single stereo stream
+/// # Remarks
+/// Channels are handed out like vanilla's S_StartSound and S_GetChannel: a sound first stops the
+/// sound from the same origin, then takes the first free channel, otherwise it replaces the first
+/// sound that is not more important than itself. If every channel holds a more important sound,
+/// the new sound is not played at all. The music, if any, is mixed in on top of the sounds.
+pub struct Mixer {
+    channels: [Option<Channel>; NUM_CHANNELS],
+    /// The master sound effect volume, 0 to `MAX_SFX_VOLUME`
+    sfx_volume: i32,
+    music: Option<MusicPlayer>,
+    /// The music volume, 0 to `MAX_MUSIC_VOLUME`
+    music_volume: i32,
+    /// The mono music samples of the block being mixed
+    music_buffer: Vec<f32>,
+    /// The sample rate of the mixed stream
+    output_rate: u32,
+}
+
+impl Mixer {
+    /// Creates a mixer with all channels free
+    /// # Arguments
+    /// * `output_rate` - The sample rate of the mixed stream; sounds are resampled to it
+    pub fn new(output_rate: u32) -> Self {
+        Self {
+            channels: Default::default(),
+            sfx_volume: MAX_SFX_VOLUME,
+            music: None,
+            music_volume: MAX_MUSIC_VOLUME,
+            music_buffer: Vec::new(),
+            output_rate,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn sfx_volume(&self) -> i32 {
+        self.sfx_volume
+    }
+
+    /// Sets the master sound effect volume, clamped to 0 to `MAX_SFX_VOLUME`
+    pub fn set_sfx_volume(&mut self, volume: i32) {
+        self.sfx_volume = volume.clamp(0, MAX_SFX_VOLUME);
+    }
+
+    pub fn music_volume(&self) -> i32 {
+        self.music_volume
+    }
+
+    /// Sets the music volume, clamped to 0 to `MAX_MUSIC_VOLUME`
+    /// # Remarks
+    /// Like vanilla's S_SetMusicVolume, the player gets 8 times the menu volume.
+    pub fn set_music_volume(&mut self, volume: i32) {
+        self.music_volume = volume.clamp(0, MAX_MUSIC_VOLUME);
+        if let Some(music) = self.music.as_mut() {
+            music.set_volume(self.music_volume * 8);
+        }
+    }
+
+    /// Starts playing a song, replacing the one that was playing
+    /// # Arguments
+    /// * `music` - The player of the song, created at the output rate of the mixer
+    pub fn play_music(&mut self, mut music: MusicPlayer) {
+        music.set_volume(self.music_volume * 8);
+        self.music = Some(music);
+    }
+
+    /// Stops the music right away
+    pub fn stop_music(&mut self) {
+        self.music = None;
+    }
+
+    /// Returns true while a song is playing; a song that doesn't loop stops at its end
+    pub fn is_music_playing(&self) -> bool {
+        self.music
+            .as_ref()
+            .is_some_and(|music| !music.is_finished())
+    }
+
+    /// Starts a sound on a channel
+    /// # Arguments
+    /// * `sound` - The sound to play
+    /// * `priority` - The priority of the sound, a lower number is more important
+    /// * `origin` - What makes the sound, if anything
+    /// * `volume` - The volume, 0 to `MAX_CHANNEL_VOLUME`
+    /// * `separation` - The stereo separation, see `NORM_SEPARATION`
+    /// # Returns
+    /// The channel the sound plays on, or `None` if all channels hold more important sounds
+    pub fn start(
+        &mut self,
+        sound: Arc<Sound>,
+        priority: i32,
+        origin: Option<Origin>,
+        volume: i32,
+        separation: i32,
+    ) -> Option<usize> {
+        let channel = self.find_channel(priority, origin)?;
+        let volume = volume.clamp(0, MAX_CHANNEL_VOLUME);
+        self.channels[channel] = Some(Channel {
+            sound,
+            origin,
+            priority,
+            volume,
+            base_volume: volume,
+            separation: separation.clamp(0, 254),
+            position: 0.0,
+        });
+        Some(channel)
+    }
+
+    /// Finds the channel for a new sound, like vanilla's S_GetChannel
+    fn find_channel(&mut self, priority: i32, origin: Option<Origin>) -> Option<usize> {
+        // Like S_StartSound, the sound of the same origin stops first, so its channel is free
+        if let Some(origin) = origin {
+            self.stop_origin(origin);
+        }
+
+        // A free channel
+        let channel = self.channels.iter().position(|channel| channel.is_none());
+
+        // Otherwise the first sound that is not more important than the new one
+        let channel = channel.or_else(|| {
+            self.channels.iter().position(|channel| {
+                channel
+                    .as_ref()
+                    .is_some_and(|channel| channel.priority >= priority)
+            })
+        })?;
+        self.stop_channel(channel);
+        Some(channel)
+    }
+
+    /// Stops the sound on a channel
+    pub fn stop_channel(&mut self, channel: usize) {
+        self.channels[channel] = None;
+    }
+
+    /// Stops the sounds of an origin, e.g. when a monster is removed
+    pub fn stop_origin(&mut self, origin: Origin) {
+        self.channels
+            .iter_mut()
+            .filter(|channel| {
+                channel
+                    .as_ref()
+                    .is_some_and(|channel| channel.origin == Some(origin))
+            })
+            .for_each(|channel| *channel = None);
+    }
+
+    /// Stops all sounds
+    pub fn stop_all(&mut self) {
+        self.channels.iter_mut().for_each(|channel| *channel = None);
+    }
+
+    /// Returns true if a sound of the origin is playing
+    pub fn is_playing(&self, origin: Origin) -> bool {
+        self.channels
+            .iter()
+            .flatten()
+            .any(|channel| channel.origin == Some(origin))
+    }
+
+    pub fn channel(&self, channel: usize) -> Option<&Channel> {
+        self.channels[channel].as_ref()
+    }
+
+    pub fn channel_mut(&mut self, channel: usize) -> Option<&mut Channel> {
+        self.channels[channel].as_mut()
+    }
+
+    /// Mixes the playing sounds and the music
+    /// # Arguments
+    /// * `output` - Interleaved stereo samples to fill, left first
+    /// # Remarks
+    /// Sounds are resampled to the output rate by repeating samples, which sounds like the
+    /// original. Channels are freed when their sound ends.
+    pub fn mix(&mut self, output: &mut [f32]) {
+        output.iter_mut().for_each(|sample| *sample = 0.0);
+        let master = self.sfx_volume as f32 / MAX_SFX_VOLUME as f32;
+
+        for slot in self.channels.iter_mut() {
+            let channel = match slot {
+                Some(channel) => channel,
+                None => continue,
+            };
+            let step = channel.sound.sample_rate as f64 / self.output_rate as f64;
+            // Like Chocolate Doom's panning, a sound straight ahead plays at half volume on each side
+            let volume = channel.volume as f32 / MAX_CHANNEL_VOLUME as f32 * master;
+            let left = (254 - channel.separation) as f32 / 254.0 * volume;
+            let right = channel.separation as f32 / 254.0 * volume;
+
+            for frame in output.chunks_exact_mut(2) {
+                let sample = match channel.sound.samples.get(channel.position as usize) {
+                    Some(&sample) => (sample as f32 - 128.0) / 128.0,
+                    None => break,
+                };
+                frame[0] += sample * left;
+                frame[1] += sample * right;
+                channel.position += step;
+            }
+
+            if channel.position as usize >= channel.sound.samples.len() {
+                *slot = None;
+            }
+        }
+
+        if let Some(music) = self.music.as_mut() {
+            // The OPL2 is mono, so the music is heard the same on both sides
+            self.music_buffer.resize(output.len() / 2, 0.0);
+            music.generate(&mut self.music_buffer);
+            for (frame, &sample) in output.chunks_exact_mut(2).zip(&self.music_buffer) {
+                frame[0] += sample;
+                frame[1] += sample;
+            }
+        }
+
+        output
+            .iter_mut()
+            .for_each(|sample| *sample = sample.clamp(-1.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound() -> Arc<Sound> {
+        Arc::new(Sound {
+            sample_rate: 11025,
+            samples: vec![128; 11025],
+        })
+    }
+
+    /// Fills every channel with a sound of its own origin at the given priority
+    fn busy_mixer(priority: i32) -> Mixer {
+        let mut mixer = Mixer::new(11025);
+        for origin in 0..NUM_CHANNELS {
+            mixer.start(sound(), priority, Some(origin), 127, NORM_SEPARATION);
+        }
+        mixer
+    }
+
+    #[test]
+    fn an_origin_replaces_its_own_sound() {
+        let mut mixer = Mixer::new(11025);
+        assert_eq!(mixer.start(sound(), 64, Some(1), 127, 128), Some(0));
+        assert_eq!(mixer.start(sound(), 64, Some(2), 127, 128), Some(1));
+        assert_eq!(mixer.start(sound(), 100, Some(2), 90, 128), Some(1));
+        assert_eq!(mixer.channel(1).map(|channel| channel.volume), Some(90));
+        assert!(mixer.channel(2).is_none());
+    }
+
+    #[test]
+    fn an_origin_replaces_its_sound_after_a_free_channel() {
+        let mut mixer = Mixer::new(11025);
+        mixer.start(sound(), 64, None, 127, 128);
+        mixer.start(sound(), 64, Some(1), 127, 128);
+        mixer.stop_channel(0);
+        mixer.start(sound(), 64, Some(1), 127, 128);
+        let playing = (0..NUM_CHANNELS)
+            .filter(|&channel| mixer.channel(channel).is_some_and(|c| c.origin == Some(1)))
+            .count();
+        assert_eq!(playing, 1);
+    }
+
+    #[test]
+    fn sounds_without_origin_never_replace_each_other() {
+        let mut mixer = Mixer::new(11025);
+        for expected in 0..3 {
+            assert_eq!(mixer.start(sound(), 64, None, 127, 128), Some(expected));
+        }
+    }
+
+    #[test]
+    fn less_important_sounds_are_dropped_when_all_channels_are_busy() {
+        let mut mixer = busy_mixer(64);
+        assert_eq!(mixer.start(sound(), 100, None, 127, 128), None);
+        assert!((0..NUM_CHANNELS).all(|channel| mixer.channel(channel).unwrap().priority == 64));
+    }
+
+    #[test]
+    fn equally_important_sounds_take_the_first_busy_channel() {
+        let mut mixer = busy_mixer(64);
+        assert_eq!(mixer.start(sound(), 64, Some(100), 127, 128), Some(0));
+        assert_eq!(mixer.channel(0).unwrap().origin, Some(100));
+
+        // Only the channels holding sounds that aren't more important are taken
+        let mut mixer = busy_mixer(32);
+        mixer.start(sound(), 100, Some(3), 127, 128);
+        assert_eq!(mixer.start(sound(), 64, None, 127, 128), Some(3));
+    }
+}
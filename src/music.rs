@@ -0,0 +1,379 @@
+use crate::wad::{LumpReader, WadError};
+
+const MUS_SIGNATURE: &[u8] = b"MUS\x1A";
+/// The amount of MUS ticks per second
+pub const MUS_TICK_RATE: u32 = 140;
+/// The MUS channel that plays percussion, like MIDI channel 9
+pub const MUS_PERCUSSION_CHANNEL: u8 = 15;
+const MIDI_PERCUSSION_CHANNEL: u8 = 9;
+/// The MIDI ticks per quarter note; at the tempo below a MIDI tick lasts exactly one MUS tick
+const MIDI_DIVISION: u16 = 70;
+/// The MIDI tempo in microseconds per quarter note
+const MIDI_TEMPO: u32 = 500_000;
+/// The MUS controller that changes the instrument
+pub const MUS_CONTROLLER_INSTRUMENT: u8 = 0;
+/// The MIDI controller numbers for MUS controllers 0 to 14; the first one is a program change
+const MIDI_CONTROLLERS: [u8; 15] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67, 120, 123, 126, 127, 121];
+
+/// A single event of a MUS score
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum MusEvent {
+    ReleaseNote {
+        channel: u8,
+        note: u8,
+    },
+    /// A note starts; the volume is the one of the previous note on the channel when the lump
+    /// doesn't give one
+    PlayNote {
+        channel: u8,
+        note: u8,
+        volume: u8,
+    },
+    /// Bends the pitch of the channel; 128 is no bend, 0 and 255 are a full tone down and up
+    PitchBend {
+        channel: u8,
+        bend: u8,
+    },
+    /// A controller without a value, like all notes off (11)
+    System {
+        channel: u8,
+        controller: u8,
+    },
+    /// A controller change; controller 0 changes the instrument
+    Controller {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    EndOfMeasure,
+    ScoreEnd,
+}
+
+/// An event along with the time it happens at
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct TimedEvent {
+    /// The time since the start of the score, in MUS ticks
+    pub time: u32,
+    pub event: MusEvent,
+}
+
+/// A song in the MUS format, the format of the D_ lumps
+/// # Remarks
+/// MUS is a compact take on MIDI made for the DMX sound library. The score is a list of events
+/// on 16 channels, with channel 15 for percussion, timed in ticks of 1/140th of a second.
+pub struct Mus {
+    pub primary_channels: u16,
+    pub secondary_channels: u16,
+    /// The instruments used by the song, as General MIDI program numbers
+    pub instruments: Vec<u16>,
+    /// The events in score order; the last one is always `ScoreEnd`
+    pub events: Vec<TimedEvent>,
+}
+
+impl Mus {
+    /// Decodes a MUS lump
+    /// # Returns
+    /// The song, an `UnsupportedFormat` error if the lump isn't a MUS lump, or a `TruncatedLump`
+    /// error if the score ends early
+    /// # Examples
+    /// ```no_run
+    /// use doom::music::Mus;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let song = wad.get_music("D_E1M1").unwrap();
+    /// std::fs::write("e1m1.mid", song.to_midi()).unwrap();
+    /// ```
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, WadError> {
+        if !data.starts_with(MUS_SIGNATURE) {
+            return Err(WadError::UnsupportedFormat {
+                name: name.to_string(),
+                offset: 0,
+                reason: "not a MUS lump".to_string(),
+            });
+        }
+
+        let mut reader = LumpReader::new(name, data);
+        reader.set_position(MUS_SIGNATURE.len());
+        let _score_length = reader.read_u16()?;
+        let score_start = reader.read_u16()? as usize;
+        let primary_channels = reader.read_u16()?;
+        let secondary_channels = reader.read_u16()?;
+        let instrument_count = reader.read_u16()?;
+        reader.read_u16()?; // Reserved
+        let instruments = (0..instrument_count)
+            .map(|_| reader.read_u16())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        reader.set_position(score_start);
+        let events = decode_score(&mut reader)?;
+        Ok(Self {
+            primary_channels,
+            secondary_channels,
+            instruments,
+            events,
+        })
+    }
+
+    /// The length of the song
+    pub fn duration_ticks(&self) -> u32 {
+        self.events.last().map_or(0, |event| event.time)
+    }
+
+    /// Converts the song to a standard MIDI file
+    /// # Remarks
+    /// The file has a single track with 70 ticks per quarter note at 120 beats per minute, so a
+    /// MIDI tick is a MUS tick. The MUS percussion channel 15 is swapped with MIDI percussion
+    /// channel 9, and the MUS controllers are mapped to their MIDI counterparts.
+    pub fn to_midi(&self) -> Vec<u8> {
+        let mut track = Vec::new();
+        // Set the tempo
+        track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&MIDI_TEMPO.to_be_bytes()[1..]);
+
+        let mut last_time = 0;
+        for timed in &self.events {
+            let message = midi_message(&timed.event);
+            if message.is_empty() {
+                continue;
+            }
+            write_variable_length(&mut track, timed.time - last_time);
+            track.extend_from_slice(&message);
+            last_time = timed.time;
+        }
+        // End of track, at the end of the score
+        write_variable_length(&mut track, self.duration_ticks() - last_time);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut midi = Vec::with_capacity(22 + track.len());
+        midi.extend_from_slice(b"MThd");
+        midi.extend_from_slice(&6u32.to_be_bytes());
+        midi.extend_from_slice(&0u16.to_be_bytes()); // Format 0, a single track
+        midi.extend_from_slice(&1u16.to_be_bytes());
+        midi.extend_from_slice(&MIDI_DIVISION.to_be_bytes());
+        midi.extend_from_slice(b"MTrk");
+        midi.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        midi.extend_from_slice(&track);
+        midi
+    }
+}
+
+/// Decodes the events of the score, up to and including the score end
+fn decode_score(reader: &mut LumpReader) -> Result<Vec<TimedEvent>, WadError> {
+    let mut events = Vec::new();
+    let mut volumes = [127u8; 16];
+    let mut time = 0;
+
+    loop {
+        let descriptor = reader.read_u8()?;
+        let channel = descriptor & 0x0F;
+        let event = match (descriptor >> 4) & 0x07 {
+            0 => MusEvent::ReleaseNote {
+                channel,
+                note: reader.read_u8()? & 0x7F,
+            },
+            1 => {
+                let note = reader.read_u8()?;
+                if note & 0x80 != 0 {
+                    volumes[channel as usize] = reader.read_u8()? & 0x7F;
+                }
+                MusEvent::PlayNote {
+                    channel,
+                    note: note & 0x7F,
+                    volume: volumes[channel as usize],
+                }
+            }
+            2 => MusEvent::PitchBend {
+                channel,
+                bend: reader.read_u8()?,
+            },
+            3 => MusEvent::System {
+                channel,
+                controller: reader.read_u8()? & 0x7F,
+            },
+            4 => MusEvent::Controller {
+                channel,
+                controller: reader.read_u8()? & 0x7F,
+                value: reader.read_u8()?.min(127),
+            },
+            5 => MusEvent::EndOfMeasure,
+            6 => MusEvent::ScoreEnd,
+            kind => {
+                return Err(WadError::UnsupportedFormat {
+                    name: reader.name().to_string(),
+                    offset: reader.position() - 1,
+                    reason: format!("unknown MUS event type {}", kind),
+                })
+            }
+        };
+        events.push(TimedEvent { time, event });
+        if event == MusEvent::ScoreEnd {
+            return Ok(events);
+        }
+
+        // The last event of a group is followed by the delay until the next group
+        if descriptor & 0x80 != 0 {
+            time += read_variable_length(reader)?;
+        }
+    }
+}
+
+/// Reads a delay, 7 bits per byte with the high bit set on all but the last byte
+fn read_variable_length(reader: &mut LumpReader) -> Result<u32, WadError> {
+    let mut value = 0u32;
+    loop {
+        let byte = reader.read_u8()?;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Writes a MIDI delta time, 7 bits per byte with the high bit set on all but the last byte
+fn write_variable_length(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+/// Gets the MIDI channel for a MUS channel; the percussion channels swap places
+pub fn midi_channel(channel: u8) -> u8 {
+    match channel {
+        MUS_PERCUSSION_CHANNEL => MIDI_PERCUSSION_CHANNEL,
+        MIDI_PERCUSSION_CHANNEL => MUS_PERCUSSION_CHANNEL,
+        _ => channel,
+    }
+}
+
+/// Converts an event to a MIDI message, or nothing for events that have no MIDI counterpart
+fn midi_message(event: &MusEvent) -> Vec<u8> {
+    match *event {
+        MusEvent::ReleaseNote { channel, note } => vec![0x80 | midi_channel(channel), note, 0],
+        MusEvent::PlayNote {
+            channel,
+            note,
+            volume,
+        } => vec![0x90 | midi_channel(channel), note, volume],
+        MusEvent::PitchBend { channel, bend } => {
+            let bend = (bend as u16) << 6;
+            vec![
+                0xE0 | midi_channel(channel),
+                (bend & 0x7F) as u8,
+                (bend >> 7) as u8,
+            ]
+        }
+        MusEvent::Controller {
+            channel,
+            controller: MUS_CONTROLLER_INSTRUMENT,
+            value,
+        } => vec![0xC0 | midi_channel(channel), value],
+        MusEvent::System {
+            channel,
+            controller,
+        } => match MIDI_CONTROLLERS.get(controller as usize) {
+            Some(&midi) if controller >= 10 => vec![0xB0 | midi_channel(channel), midi, 0],
+            _ => Vec::new(),
+        },
+        MusEvent::Controller {
+            channel,
+            controller,
+            value,
+        } => match MIDI_CONTROLLERS.get(controller as usize) {
+            Some(&midi) if controller < 10 => vec![0xB0 | midi_channel(channel), midi, value],
+            _ => Vec::new(),
+        },
+        MusEvent::EndOfMeasure | MusEvent::ScoreEnd => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a MUS lump without instruments around a score
+    fn mus_lump(score: &[u8]) -> Vec<u8> {
+        let mut data = MUS_SIGNATURE.to_vec();
+        for value in [score.len() as u16, 16, 1, 0, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(score);
+        data
+    }
+
+    fn events(score: &[u8]) -> Vec<MusEvent> {
+        let song = Mus::decode("D_TEST", &mus_lump(score)).unwrap();
+        song.events.iter().map(|timed| timed.event).collect()
+    }
+
+    #[test]
+    fn notes_without_volume_use_the_last_volume_of_their_channel() {
+        let events = events(
+            &[
+                // Note 60 at volume 100, then release it
+                &[0x10, 0x80 | 60, 100, 0x00, 60][..],
+                // Note 62 at the same volume
+                &[0x10, 62],
+                // Another channel starts at full volume
+                &[0x11, 64, 0x60],
+            ]
+            .concat(),
+        );
+        let volumes: Vec<_> = events
+            .iter()
+            .filter_map(|event| match *event {
+                MusEvent::PlayNote { volume, .. } => Some(volume),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(volumes, vec![100, 100, 127]);
+    }
+
+    #[test]
+    fn delays_follow_the_last_event_of_a_group() {
+        let song = Mus::decode(
+            "D_TEST",
+            &mus_lump(
+                &[
+                    // Note 60, then wait 128 ticks
+                    &[0x90, 60, 0x81, 0x00][..],
+                    // Release it, then wait 5 ticks
+                    &[0x80, 60, 0x05],
+                    &[0x60],
+                ]
+                .concat(),
+            ),
+        )
+        .unwrap();
+        let times: Vec<_> = song.events.iter().map(|timed| timed.time).collect();
+        assert_eq!(times, vec![0, 128, 133]);
+        assert_eq!(song.duration_ticks(), 133);
+
+        // The MIDI track holds the same delays, after the tempo
+        let midi = song.to_midi();
+        assert_eq!(
+            &midi[29..],
+            &[0x00, 0x90, 60, 127, 0x81, 0x00, 0x80, 60, 0, 0x05, 0xFF, 0x2F, 0x00]
+        );
+    }
+
+    #[test]
+    fn percussion_channels_swap_places() {
+        let song = Mus::decode("D_TEST", &mus_lump(&[0x1F, 36, 0x19, 60, 0x60])).unwrap();
+        let midi = song.to_midi();
+        assert_eq!(&midi[29..37], &[0x00, 0x99, 36, 127, 0x00, 0x9F, 60, 127]);
+    }
+
+    #[test]
+    fn truncated_scores_are_rejected() {
+        for score in [&[0x10][..], &[0x90, 60, 0x81]] {
+            assert!(matches!(
+                Mus::decode("D_TEST", &mus_lump(score)),
+                Err(WadError::TruncatedLump { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::f64::consts::TAU;
+
+/// The sample rate of a real OPL2, the chip clock divided by 72
+pub const OPL_RATE: f64 = 49716.0;
+/// The amount of melodic channels of an OPL2
+pub const OPL_NUM_CHANNELS: usize = 9;
+/// Each channel has two operators, a modulator and a carrier
+const NUM_OPERATORS: usize = OPL_NUM_CHANNELS * 2;
+
+/// The envelope attenuation of a silent operator; one step is 0.1875 dB
+const MAX_ATTENUATION: f64 = 511.0;
+const ATTENUATION_STEP_DB: f64 = 0.1875;
+/// The time an attack takes at rate 1, the slowest rate that moves at all
+const ATTACK_TIME_MS: f64 = 2826.24;
+/// The time a decay from full volume to silence takes at rate 1
+const DECAY_TIME_MS: f64 = 39280.64;
+/// The frequency multipliers, indexed by the MULT bits of register 0x20
+const MULTIPLIERS: [f64; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+/// The key scale attenuation at block 8, indexed by the high four bits of the frequency number
+const KSL_ROM: [i32; 16] = [
+    0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64,
+];
+/// How far the key scale attenuation is shifted down for each KSL setting; 8 turns it off
+const KSL_SHIFT: [u32; 4] = [8, 1, 2, 0];
+/// The frequencies of the tremolo and vibrato oscillators in Hz
+const TREMOLO_RATE: f64 = 3.7;
+const VIBRATO_RATE: f64 = 6.1;
+
+/// Converts a register offset (0x00 to 0x15) to an operator, or `None` for the unused offsets
+fn operator_index(offset: u8) -> Option<usize> {
+    let (group, slot) = ((offset / 8) as usize, (offset % 8) as usize);
+    match group < 3 && slot < 6 {
+        true => Some(group * 6 + slot),
+        false => None,
+    }
+}
+
+/// The register offset of the modulator of a channel; the carrier is 3 higher
+pub fn modulator_offset(channel: usize) -> u8 {
+    ((channel / 3) * 8 + channel % 3) as u8
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+/// One of the two sine oscillators of a channel
+#[derive(Debug, Clone)]
+struct Operator {
+    tremolo: bool,
+    vibrato: bool,
+    /// Whether the envelope holds at the sustain level until the key is released
+    sustaining: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    total_level: u8,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+    waveform: u8,
+    /// The position in the wave, in cycles
+    phase: f64,
+    stage: EnvelopeStage,
+    /// The envelope attenuation, 0 (full volume) to `MAX_ATTENUATION`
+    envelope: f64,
+    /// The last two outputs, used for feedback
+    previous: [f64; 2],
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            tremolo: false,
+            vibrato: false,
+            sustaining: false,
+            key_scale_rate: false,
+            multiplier: 0,
+            key_scale_level: 0,
+            total_level: 0,
+            attack_rate: 0,
+            decay_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            waveform: 0,
+            phase: 0.0,
+            stage: EnvelopeStage::Off,
+            envelope: MAX_ATTENUATION,
+            previous: [0.0; 2],
+        }
+    }
+}
+
+/// The frequency and key state of a channel, shared by its operators
+#[derive(Debug, Default, Copy, Clone)]
+struct Channel {
+    /// The 10-bit frequency number
+    fnum: u16,
+    /// The octave, 0 to 7
+    block: u8,
+    key_on: bool,
+    /// The modulator feedback, 0 (none) to 7
+    feedback: u8,
+    /// Whether both operators are heard (additive synthesis) rather than the modulator
+    /// modulating the carrier (FM synthesis)
+    additive: bool,
+}
+
+impl Channel {
+    /// The frequency of the channel in Hz, before the operator multipliers
+    fn frequency(&self) -> f64 {
+        self.fnum as f64 * OPL_RATE / (1u32 << (20 - self.block)) as f64
+    }
+
+    /// The key code that selects the envelope rate scaling
+    fn key_code(&self, note_select: bool) -> u8 {
+        let bit = match note_select {
+            true => (self.fnum >> 8) & 1,
+            false => (self.fnum >> 9) & 1,
+        };
+        self.block * 2 + bit as u8
+    }
+}
+
+/// A software emulation of the Yamaha YM3812 (OPL2) FM synthesis chip
+/// # Remarks
+/// The chip is driven the same way as the real one, by writing its registers. It renders at any
+/// sample rate rather than the native 49716 Hz, and it computes its waves and envelopes in
+/// floating point, so it sounds like the original without being cycle exact. The rhythm mode and
+/// the timers are not emulated; Doom doesn't use them.
+/// # Examples
+/// ```
+/// use doom::opl::Opl;
+/// let mut opl = Opl::new(44100);
+/// opl.write(0x20, 0x01); // Modulator multiplier 1
+/// opl.write(0x23, 0x01); // Carrier multiplier 1
+/// opl.write(0x63, 0xF0); // Carrier attack rate 15
+/// opl.write(0xA0, 0x44); // Frequency number low bits
+/// opl.write(0xB0, 0x32); // Key on, block 4
+/// let mut samples = vec![0.0; 1024];
+/// opl.generate(&mut samples);
+/// ```
+pub struct Opl {
+    sample_rate: f64,
+    operators: [Operator; NUM_OPERATORS],
+    channels: [Channel; OPL_NUM_CHANNELS],
+    /// Whether the operators can use waveforms other than the sine, register 0x01 bit 5
+    waveform_select: bool,
+    /// Which frequency bit selects the key scale, register 0x08 bit 6
+    note_select: bool,
+    /// The tremolo depth: 4.8 dB when set, 1 dB otherwise
+    deep_tremolo: bool,
+    /// The vibrato depth: 14 cents when set, 7 cents otherwise
+    deep_vibrato: bool,
+    /// The time since the chip was created, in seconds, for the tremolo and vibrato oscillators
+    time: f64,
+}
+
+impl Opl {
+    /// Creates a chip with all registers cleared and all operators silent
+    /// # Arguments
+    /// * `sample_rate` - The sample rate to render at
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            operators: Default::default(),
+            channels: Default::default(),
+            waveform_select: false,
+            note_select: false,
+            deep_tremolo: false,
+            deep_vibrato: false,
+            time: 0.0,
+        }
+    }
+
+    /// Writes a register
+    /// # Arguments
+    /// * `register` - The register address, 0x01 to 0xF5
+    /// * `value` - The value to write
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x01 => self.waveform_select = value & 0x20 != 0,
+            0x08 => self.note_select = value & 0x40 != 0,
+            0x20..=0x35 => {
+                if let Some(operator) = self.operator_mut(register - 0x20) {
+                    operator.tremolo = value & 0x80 != 0;
+                    operator.vibrato = value & 0x40 != 0;
+                    operator.sustaining = value & 0x20 != 0;
+                    operator.key_scale_rate = value & 0x10 != 0;
+                    operator.multiplier = value & 0x0F;
+                }
+            }
+            0x40..=0x55 => {
+                if let Some(operator) = self.operator_mut(register - 0x40) {
+                    operator.key_scale_level = value >> 6;
+                    operator.total_level = value & 0x3F;
+                }
+            }
+            0x60..=0x75 => {
+                if let Some(operator) = self.operator_mut(register - 0x60) {
+                    operator.attack_rate = value >> 4;
+                    operator.decay_rate = value & 0x0F;
+                }
+            }
+            0x80..=0x95 => {
+                if let Some(operator) = self.operator_mut(register - 0x80) {
+                    operator.sustain_level = value >> 4;
+                    operator.release_rate = value & 0x0F;
+                }
+            }
+            0xA0..=0xA8 => {
+                let channel = &mut self.channels[(register - 0xA0) as usize];
+                channel.fnum = (channel.fnum & 0x300) | value as u16;
+            }
+            0xB0..=0xB8 => {
+                let index = (register - 0xB0) as usize;
+                let channel = &mut self.channels[index];
+                channel.fnum = (channel.fnum & 0xFF) | (((value & 0x03) as u16) << 8);
+                channel.block = (value >> 2) & 0x07;
+                let key_on = value & 0x20 != 0;
+                if key_on != channel.key_on {
+                    channel.key_on = key_on;
+                    self.set_key(index, key_on);
+                }
+            }
+            0xBD => {
+                self.deep_tremolo = value & 0x80 != 0;
+                self.deep_vibrato = value & 0x40 != 0;
+            }
+            0xC0..=0xC8 => {
+                let channel = &mut self.channels[(register - 0xC0) as usize];
+                channel.feedback = (value >> 1) & 0x07;
+                channel.additive = value & 0x01 != 0;
+            }
+            0xE0..=0xF5 => {
+                if let Some(operator) = self.operator_mut(register - 0xE0) {
+                    operator.waveform = value & 0x03;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Silences all channels and clears all registers
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sample_rate as u32);
+    }
+
+    fn operator_mut(&mut self, offset: u8) -> Option<&mut Operator> {
+        operator_index(offset).map(|index| &mut self.operators[index])
+    }
+
+    /// The operators of a channel, modulator first
+    fn channel_operators(channel: usize) -> (usize, usize) {
+        let modulator = (channel / 3) * 6 + channel % 3;
+        (modulator, modulator + 3)
+    }
+
+    /// Starts or releases the envelopes of the operators of a channel
+    fn set_key(&mut self, channel: usize, key_on: bool) {
+        let (modulator, carrier) = Self::channel_operators(channel);
+        for index in [modulator, carrier] {
+            let operator = &mut self.operators[index];
+            if key_on {
+                operator.phase = 0.0;
+                operator.stage = EnvelopeStage::Attack;
+            } else if operator.stage != EnvelopeStage::Off {
+                operator.stage = EnvelopeStage::Release;
+            }
+        }
+    }
+
+    /// Renders mono samples, replacing the contents of `output`
+    /// # Remarks
+    /// A single channel at full volume peaks at 1/8, like the real chip, so all channels together
+    /// rarely clip.
+    pub fn generate(&mut self, output: &mut [f32]) {
+        let step = 1.0 / self.sample_rate;
+        for sample in output.iter_mut() {
+            // The tremolo oscillator goes from 0 to 1 and the vibrato from -1 to 1
+            let tremolo = (1.0 - (TAU * TREMOLO_RATE * self.time).cos()) / 2.0;
+            let vibrato = (TAU * VIBRATO_RATE * self.time).sin();
+            let mut mixed = 0.0;
+            for channel in 0..OPL_NUM_CHANNELS {
+                mixed += self.channel_sample(channel, tremolo, vibrato);
+            }
+            *sample = (mixed / 8.0) as f32;
+            self.time += step;
+        }
+    }
+
+    /// Renders the next sample of a channel
+    fn channel_sample(&mut self, index: usize, tremolo: f64, vibrato: f64) -> f64 {
+        let channel = self.channels[index];
+        let (modulator, carrier) = Self::channel_operators(index);
+        if self.operators[modulator].stage == EnvelopeStage::Off
+            && self.operators[carrier].stage == EnvelopeStage::Off
+        {
+            return 0.0;
+        }
+
+        // The feedback phase modulation reaches two cycles at feedback 7
+        let feedback = match channel.feedback {
+            0 => 0.0,
+            feedback => {
+                let previous = self.operators[modulator].previous;
+                (previous[0] + previous[1]) * 2f64.powi(feedback as i32 - 7)
+            }
+        };
+        let modulator_out = self.operator_sample(modulator, &channel, feedback, tremolo, vibrato);
+        let operator = &mut self.operators[modulator];
+        operator.previous = [operator.previous[1], modulator_out];
+
+        match channel.additive {
+            true => modulator_out + self.operator_sample(carrier, &channel, 0.0, tremolo, vibrato),
+            // The modulator shifts the phase of the carrier by up to four cycles
+            false => self.operator_sample(carrier, &channel, modulator_out * 4.0, tremolo, vibrato),
+        }
+    }
+
+    /// Renders the next sample of an operator and advances its phase and envelope
+    /// # Arguments
+    /// * `index` - The operator
+    /// * `channel` - The channel the operator belongs to
+    /// * `modulation` - The phase modulation, in cycles
+    /// * `tremolo` - The tremolo oscillator, 0 to 1
+    /// * `vibrato` - The vibrato oscillator, -1 to 1
+    fn operator_sample(
+        &mut self,
+        index: usize,
+        channel: &Channel,
+        modulation: f64,
+        tremolo: f64,
+        vibrato: f64,
+    ) -> f64 {
+        let key_code = channel.key_code(self.note_select);
+        let waveform = match self.waveform_select {
+            true => self.operators[index].waveform,
+            false => 0,
+        };
+        let tremolo_depth = match self.deep_tremolo {
+            true => 4.8,
+            false => 1.0,
+        } / ATTENUATION_STEP_DB;
+        let vibrato_cents = match self.deep_vibrato {
+            true => 14.0,
+            false => 7.0,
+        };
+        let sample_rate = self.sample_rate;
+        let operator = &mut self.operators[index];
+        operator.update_envelope(key_code, sample_rate);
+        if operator.stage == EnvelopeStage::Off {
+            return 0.0;
+        }
+
+        let mut attenuation = operator.envelope
+            + (operator.total_level as f64) * 4.0
+            + key_scale_attenuation(channel, operator.key_scale_level);
+        if operator.tremolo {
+            attenuation += tremolo * tremolo_depth;
+        }
+        let amplitude = match attenuation < MAX_ATTENUATION {
+            true => 10f64.powf(-attenuation * ATTENUATION_STEP_DB / 20.0),
+            false => 0.0,
+        };
+        let output = wave(waveform, operator.phase + modulation) * amplitude;
+
+        let mut frequency = channel.frequency() * MULTIPLIERS[operator.multiplier as usize];
+        if operator.vibrato {
+            frequency *= 2f64.powf(vibrato * vibrato_cents / 1200.0);
+        }
+        operator.phase = (operator.phase + frequency / sample_rate).fract();
+        output
+    }
+}
+
+impl Operator {
+    /// The rate of an envelope stage, scaled up for higher notes
+    fn effective_rate(&self, rate: u8, key_code: u8) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+        let scaling = match self.key_scale_rate {
+            true => key_code,
+            false => key_code >> 2,
+        };
+        (rate * 4 + scaling).min(63)
+    }
+
+    /// Advances the envelope by one sample
+    fn update_envelope(&mut self, key_code: u8, sample_rate: f64) {
+        // The attenuation a decay of the given rate adds per sample
+        let decay_step = |rate: u8| match rate {
+            0 => 0.0,
+            rate => MAX_ATTENUATION * 1000.0 / (rate_time(DECAY_TIME_MS, rate) * sample_rate),
+        };
+        let sustain_level = match self.sustain_level {
+            15 => 31.0 * 16.0,
+            level => level as f64 * 16.0,
+        };
+
+        match self.stage {
+            EnvelopeStage::Attack => {
+                let rate = self.effective_rate(self.attack_rate, key_code);
+                if rate >= 60 {
+                    self.envelope = 0.0;
+                } else if rate > 0 {
+                    // The attack is exponential, quick at first and slowing down near full volume
+                    let time = rate_time(ATTACK_TIME_MS, rate) / 1000.0;
+                    let factor = (-(MAX_ATTENUATION + 1.0).ln() / (time * sample_rate)).exp();
+                    self.envelope = (self.envelope + 1.0) * factor - 1.0;
+                }
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.envelope += decay_step(self.effective_rate(self.decay_rate, key_code));
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => {
+                // Without the sustain bit the sound fades out at the release rate, like a piano
+                if !self.sustaining {
+                    self.envelope += decay_step(self.effective_rate(self.release_rate, key_code));
+                }
+            }
+            EnvelopeStage::Release => {
+                self.envelope += decay_step(self.effective_rate(self.release_rate, key_code));
+            }
+            EnvelopeStage::Off => {}
+        }
+
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            if self.stage != EnvelopeStage::Attack {
+                self.stage = EnvelopeStage::Off;
+            }
+        }
+    }
+}
+
+/// The time an envelope stage takes at an effective rate; every four rates halve the time
+fn rate_time(base: f64, rate: u8) -> f64 {
+    let speed = 2f64.powi((rate >> 2) as i32 - 1) * (1.0 + (rate & 3) as f64 / 4.0);
+    base / speed
+}
+
+/// The attenuation of higher notes, in envelope steps
+fn key_scale_attenuation(channel: &Channel, key_scale_level: u8) -> f64 {
+    let attenuation =
+        ((KSL_ROM[(channel.fnum >> 6) as usize] << 2) - ((8 - channel.block as i32) << 5)).max(0);
+    (attenuation >> KSL_SHIFT[key_scale_level as usize]) as f64
+}
+
+/// Samples one of the four OPL2 waveforms
+/// # Arguments
+/// * `waveform` - 0 is a sine, 1 the positive half of a sine, 2 the absolute value of a sine and
+///   3 the rising quarters of the absolute value of a sine
+/// * `phase` - The position in the wave, in cycles
+fn wave(waveform: u8, phase: f64) -> f64 {
+    let phase = phase.rem_euclid(1.0);
+    let sine = (TAU * phase).sin();
+    match waveform {
+        1 if phase >= 0.5 => 0.0,
+        2 => sine.abs(),
+        3 if phase % 0.5 >= 0.25 => 0.0,
+        3 => sine.abs(),
+        _ => sine,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_offsets_address_the_operators_of_each_channel() {
+        for channel in 0..OPL_NUM_CHANNELS {
+            let modulator = operator_index(modulator_offset(channel)).unwrap();
+            let carrier = operator_index(modulator_offset(channel) + 3).unwrap();
+            assert_eq!((modulator, carrier), Opl::channel_operators(channel));
+        }
+        // Offsets 0x06, 0x07, 0x0E and 0x0F have no operator
+        for offset in [0x06, 0x07, 0x0E, 0x0F] {
+            assert_eq!(operator_index(offset), None);
+        }
+    }
+
+    #[test]
+    fn operator_registers_are_decoded() {
+        let mut opl = Opl::new(44100);
+        // The carrier of channel 4, offset 0x0C
+        opl.write(0x2C, 0xB4);
+        opl.write(0x4C, 0x95);
+        opl.write(0x6C, 0xF2);
+        opl.write(0x8C, 0x7A);
+        opl.write(0xEC, 0x07);
+        let operator = &opl.operators[Opl::channel_operators(4).1];
+        assert!(operator.tremolo && !operator.vibrato && operator.sustaining);
+        assert!(operator.key_scale_rate);
+        assert_eq!(operator.multiplier, 4);
+        assert_eq!((operator.key_scale_level, operator.total_level), (2, 0x15));
+        assert_eq!((operator.attack_rate, operator.decay_rate), (15, 2));
+        assert_eq!((operator.sustain_level, operator.release_rate), (7, 10));
+        assert_eq!(operator.waveform, 3);
+        // No other operator changed
+        let changed = opl
+            .operators
+            .iter()
+            .filter(|operator| operator.multiplier != 0);
+        assert_eq!(changed.count(), 1);
+    }
+
+    #[test]
+    fn channel_registers_set_the_frequency_and_key() {
+        let mut opl = Opl::new(44100);
+        opl.write(0xC2, 0x0B);
+        opl.write(0xA2, 0x44);
+        opl.write(0xB2, 0x32);
+        let channel = opl.channels[2];
+        assert_eq!((channel.feedback, channel.additive), (5, true));
+        assert_eq!((channel.fnum, channel.block), (0x244, 4));
+        assert!(channel.key_on);
+        let (modulator, carrier) = Opl::channel_operators(2);
+        assert_eq!(opl.operators[modulator].stage, EnvelopeStage::Attack);
+        assert_eq!(opl.operators[carrier].stage, EnvelopeStage::Attack);
+
+        // Keying off releases the envelopes, and the frequency number keeps its low bits
+        opl.write(0xB2, 0x11);
+        assert_eq!((opl.channels[2].fnum, opl.channels[2].block), (0x144, 4));
+        assert_eq!(opl.operators[carrier].stage, EnvelopeStage::Release);
+    }
+
+    #[test]
+    fn keyed_channels_make_sound() {
+        let mut opl = Opl::new(44100);
+        opl.write(0x23, 0x21); // Sustaining carrier, multiplier 1
+        opl.write(0x63, 0xF0); // Fastest attack
+        opl.write(0xA0, 0x44);
+        opl.write(0xB0, 0x32);
+        let mut samples = vec![0.0; 441];
+        opl.generate(&mut samples);
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.1 && peak <= 1.0 / 8.0 + f32::EPSILON);
+
+        opl.reset();
+        opl.generate(&mut samples);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+    }
+}
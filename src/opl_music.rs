@@ -0,0 +1,615 @@
+use crate::genmidi::{GenMidi, GenMidiOperator, GenMidiVoice};
+use crate::music::{
+    Mus, MusEvent, MUS_CONTROLLER_INSTRUMENT, MUS_PERCUSSION_CHANNEL, MUS_TICK_RATE,
+};
+use crate::opl::{modulator_offset, Opl, OPL_NUM_CHANNELS, OPL_RATE};
+use std::sync::Arc;
+
+/// The highest music volume of the player, vanilla's sound menu volume times 8
+pub const MAX_PLAYER_VOLUME: i32 = 127;
+const MUS_NUM_CHANNELS: usize = 16;
+const MUS_CONTROLLER_VOLUME: u8 = 3;
+const MUS_ALL_SOUNDS_OFF: u8 = 10;
+const MUS_ALL_NOTES_OFF: u8 = 11;
+const MUS_RESET_CONTROLLERS: u8 = 14;
+/// The volume of a channel until the song sets one
+const DEFAULT_CHANNEL_VOLUME: u8 = 100;
+/// The loudest output level of an operator; the level register is an attenuation
+const MAX_OPERATOR_LEVEL: u8 = 0x3F;
+/// The first entry of the frequency table that belongs to the octave repeated for higher notes
+const FREQUENCY_OCTAVE_START: usize = 284;
+/// The entries of the frequency table in an octave, one per 1/32 semitone
+const FREQUENCY_OCTAVE: usize = 12 * 32;
+/// MIDI volumes to OPL levels, from Chocolate Doom's i_oplmusic.c, which matched the DMX library
+const VOLUME_MAPPING: [u8; 128] = [
+    0, 1, 3, 5, 6, 8, 10, 11, 13, 14, 16, 17, 19, 20, 22, 23, 25, 26, 27, 29, 30, 32, 33, 34, 36,
+    37, 39, 41, 43, 45, 47, 49, 50, 52, 54, 55, 57, 59, 60, 61, 63, 64, 66, 67, 68, 69, 71, 72, 73,
+    74, 75, 76, 77, 79, 80, 81, 82, 83, 84, 84, 85, 86, 87, 88, 89, 90, 91, 92, 92, 93, 94, 95, 96,
+    96, 97, 98, 99, 99, 100, 101, 101, 102, 103, 103, 104, 105, 105, 106, 107, 107, 108, 109, 109,
+    110, 110, 111, 112, 112, 113, 113, 114, 114, 115, 115, 116, 117, 117, 118, 118, 119, 119, 120,
+    120, 121, 121, 122, 122, 123, 123, 123, 124, 124, 125, 125, 126, 126, 127, 127,
+];
+
+/// The state of a MUS channel
+#[derive(Debug, Copy, Clone)]
+struct ChannelState {
+    /// The General MIDI program of the melodic instrument
+    program: u8,
+    volume: u8,
+    /// The pitch bend in 1/32 semitones, -64 to 63
+    bend: i32,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            program: 0,
+            volume: DEFAULT_CHANNEL_VOLUME,
+            bend: 0,
+        }
+    }
+}
+
+/// A note playing on an OPL channel
+#[derive(Debug, Copy, Clone)]
+struct Voice {
+    /// The MUS channel the note belongs to
+    channel: u8,
+    /// The note as the score played it, used to release it
+    key: u8,
+    /// The note heard, which is fixed for some percussion instruments
+    note: u8,
+    settings: GenMidiVoice,
+    /// Whether the instrument ignores the base note offset
+    fixed: bool,
+    /// Whether this is the second voice of a double voice instrument
+    second: bool,
+    /// The detune of the second voice in 1/32 semitones
+    fine_tuning: i32,
+    volume: u8,
+    /// The frequency number and block as written to registers 0xA0 and 0xB0, without the key bit
+    frequency: u16,
+}
+
+/// Plays a MUS song on an emulated OPL2 with the instruments of the GENMIDI bank
+/// # Remarks
+/// This follows the OPL music driver of Chocolate Doom, which reproduces the DMX library: each
+/// note takes one of the 9 OPL channels, or two for double voice instruments when a channel is
+/// free. When all channels are taken, the second voices and the notes of the highest MUS
+/// channels are cut off first.
+/// # Examples
+/// ```no_run
+/// use doom::opl_music::MusicPlayer;
+/// use doom::wad::WadFile;
+/// let wad = WadFile::load("doom1.wad").unwrap();
+/// let song = wad.get_music("D_E1M1").unwrap();
+/// let mut player = MusicPlayer::new(song, wad.get_genmidi().unwrap(), 44100, true);
+/// let mut samples = vec![0.0; 44100];
+/// player.generate(&mut samples);
+/// ```
+pub struct MusicPlayer {
+    opl: Opl,
+    bank: Arc<GenMidi>,
+    song: Arc<Mus>,
+    /// Whether the song starts over when it ends, as level music does
+    looping: bool,
+    /// The music volume, 0 to `MAX_PLAYER_VOLUME`
+    volume: i32,
+    channels: [ChannelState; MUS_NUM_CHANNELS],
+    voices: [Option<Voice>; OPL_NUM_CHANNELS],
+    /// The index of the next event of the score
+    next_event: usize,
+    /// The current time in the score, in MUS ticks
+    tick: u32,
+    samples_per_tick: f64,
+    samples_until_tick: f64,
+    finished: bool,
+}
+
+impl MusicPlayer {
+    /// Creates a player at the start of a song
+    /// # Arguments
+    /// * `song` - The song to play
+    /// * `bank` - The instruments, from the GENMIDI lump
+    /// * `sample_rate` - The sample rate to render at
+    /// * `looping` - Whether the song starts over when it ends
+    pub fn new(song: Arc<Mus>, bank: Arc<GenMidi>, sample_rate: u32, looping: bool) -> Self {
+        let mut opl = Opl::new(sample_rate);
+        // Like DMX: enable the waveforms and select the key scale with the 9th frequency bit
+        opl.write(0x01, 0x20);
+        opl.write(0x08, 0x40);
+        Self {
+            opl,
+            bank,
+            song,
+            looping,
+            volume: MAX_PLAYER_VOLUME,
+            channels: Default::default(),
+            voices: Default::default(),
+            next_event: 0,
+            tick: 0,
+            samples_per_tick: sample_rate as f64 / MUS_TICK_RATE as f64,
+            samples_until_tick: 0.0,
+            finished: false,
+        }
+    }
+
+    /// Returns true when a song that doesn't loop has played to its end
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Sets the music volume, clamped to 0 to `MAX_PLAYER_VOLUME`
+    /// # Remarks
+    /// The playing notes change volume right away.
+    pub fn set_volume(&mut self, volume: i32) {
+        self.volume = volume.clamp(0, MAX_PLAYER_VOLUME);
+        for index in 0..OPL_NUM_CHANNELS {
+            self.update_voice_volume(index);
+        }
+    }
+
+    /// Renders mono samples, replacing the contents of `output`
+    pub fn generate(&mut self, output: &mut [f32]) {
+        let mut position = 0;
+        while position < output.len() {
+            if self.samples_until_tick <= 0.0 {
+                self.play_tick();
+                self.samples_until_tick += self.samples_per_tick;
+            }
+            let count = (self.samples_until_tick.ceil() as usize).clamp(1, output.len() - position);
+            self.opl.generate(&mut output[position..position + count]);
+            position += count;
+            self.samples_until_tick -= count as f64;
+        }
+    }
+
+    /// Plays the events of the current tick and moves on to the next one
+    fn play_tick(&mut self) {
+        if self.finished {
+            return;
+        }
+        let song = self.song.clone();
+        while let Some(timed) = song.events.get(self.next_event) {
+            if timed.time > self.tick {
+                break;
+            }
+            self.next_event += 1;
+            match timed.event {
+                // An empty song would loop forever without moving on
+                MusEvent::ScoreEnd if self.looping && song.duration_ticks() > 0 => {
+                    self.release_all();
+                    self.next_event = 0;
+                    self.tick = 0;
+                }
+                MusEvent::ScoreEnd => {
+                    self.release_all();
+                    self.finished = true;
+                    return;
+                }
+                event => self.play_event(event),
+            }
+        }
+        self.tick += 1;
+    }
+
+    fn play_event(&mut self, event: MusEvent) {
+        match event {
+            MusEvent::ReleaseNote { channel, note } => self.release_note(channel, note),
+            MusEvent::PlayNote {
+                channel,
+                note,
+                volume,
+            } => self.play_note(channel, note, volume),
+            MusEvent::PitchBend { channel, bend } => {
+                // The MIDI pitch bend range of a full tone up or down, in 1/32 semitones
+                self.channels[channel as usize].bend = (bend >> 1) as i32 - 64;
+                for index in 0..OPL_NUM_CHANNELS {
+                    if self.voices[index].is_some_and(|voice| voice.channel == channel) {
+                        self.update_voice_frequency(index);
+                    }
+                }
+            }
+            MusEvent::System {
+                channel,
+                controller,
+            } => match controller {
+                MUS_ALL_SOUNDS_OFF | MUS_ALL_NOTES_OFF => self.release_channel(channel),
+                MUS_RESET_CONTROLLERS => {
+                    self.channels[channel as usize] = ChannelState {
+                        program: self.channels[channel as usize].program,
+                        ..Default::default()
+                    }
+                }
+                _ => {}
+            },
+            MusEvent::Controller {
+                channel,
+                controller,
+                value,
+            } => match controller {
+                MUS_CONTROLLER_INSTRUMENT => self.channels[channel as usize].program = value,
+                MUS_CONTROLLER_VOLUME => {
+                    self.channels[channel as usize].volume = value;
+                    for index in 0..OPL_NUM_CHANNELS {
+                        if self.voices[index].is_some_and(|voice| voice.channel == channel) {
+                            self.update_voice_volume(index);
+                        }
+                    }
+                }
+                // Panning, effects and pedals don't apply to a mono OPL2
+                _ => {}
+            },
+            MusEvent::EndOfMeasure | MusEvent::ScoreEnd => {}
+        }
+    }
+
+    /// Starts a note on one or two free OPL channels
+    fn play_note(&mut self, channel: u8, key: u8, volume: u8) {
+        let instrument = match channel {
+            MUS_PERCUSSION_CHANNEL => match self.bank.percussion(key) {
+                Some(instrument) => instrument,
+                None => return,
+            },
+            _ => self.bank.melodic(self.channels[channel as usize].program),
+        };
+        let note = match instrument.is_fixed() {
+            true => instrument.fixed_note,
+            false => key,
+        };
+        let voice = Voice {
+            channel,
+            key,
+            note,
+            settings: instrument.voices[0],
+            fixed: instrument.is_fixed(),
+            second: false,
+            fine_tuning: 0,
+            volume,
+            frequency: 0,
+        };
+        let second = Voice {
+            settings: instrument.voices[1],
+            second: true,
+            fine_tuning: instrument.fine_tuning as i32 / 2 - 64,
+            ..voice
+        };
+        let double_voice = instrument.is_double_voice();
+
+        let index = self.free_voice().unwrap_or_else(|| self.replace_voice());
+        self.start_voice(index, voice);
+        // The second voice only plays when there is room for it
+        if double_voice {
+            if let Some(index) = self.free_voice() {
+                self.start_voice(index, second);
+            }
+        }
+    }
+
+    fn free_voice(&self) -> Option<usize> {
+        self.voices.iter().position(|voice| voice.is_none())
+    }
+
+    /// Frees a channel for a new note when all are taken
+    /// # Remarks
+    /// Second voices are cut off first, then the notes of the highest MUS channel.
+    fn replace_voice(&mut self) -> usize {
+        let index = (0..OPL_NUM_CHANNELS)
+            .max_by_key(|&index| {
+                self.voices[index].map_or((false, 0), |voice| (voice.second, voice.channel))
+            })
+            .expect("There is at least one voice");
+        self.release_voice(index);
+        index
+    }
+
+    /// Programs the instrument of a voice and keys it on
+    fn start_voice(&mut self, index: usize, voice: Voice) {
+        let modulator = modulator_offset(index);
+        let carrier = modulator + 3;
+        let settings = voice.settings;
+        // In additive mode the modulator is heard, so its level follows the note volume as well
+        let additive = settings.feedback & 0x01 != 0;
+        self.write_operator(carrier, &settings.carrier, true);
+        self.write_operator(modulator, &settings.modulator, additive);
+        self.opl.write(0xC0 + index as u8, settings.feedback);
+
+        self.voices[index] = Some(voice);
+        self.update_voice_volume(index);
+        self.update_voice_frequency(index);
+    }
+
+    /// Writes the registers of an operator
+    /// # Arguments
+    /// * `offset` - The register offset of the operator
+    /// * `operator` - The register values
+    /// * `silent` - Whether to start at the lowest level; the volume sets the real level later
+    fn write_operator(&mut self, offset: u8, operator: &GenMidiOperator, silent: bool) {
+        let level = match silent {
+            true => operator.scale | MAX_OPERATOR_LEVEL,
+            false => operator.scale | operator.level,
+        };
+        self.opl.write(0x40 + offset, level);
+        self.opl.write(0x20 + offset, operator.tremolo);
+        self.opl.write(0x60 + offset, operator.attack);
+        self.opl.write(0x80 + offset, operator.sustain);
+        self.opl.write(0xE0 + offset, operator.waveform);
+    }
+
+    /// Sets the level of a voice from the note, channel and music volumes
+    fn update_voice_volume(&mut self, index: usize) {
+        let voice = match self.voices[index] {
+            Some(voice) => voice,
+            None => return,
+        };
+        let channel_volume =
+            self.channels[voice.channel as usize].volume as i32 * self.volume / MAX_PLAYER_VOLUME;
+        let midi_volume = 2 * (VOLUME_MAPPING[channel_volume as usize] as i32 + 1);
+        let full_volume = (VOLUME_MAPPING[voice.volume as usize] as i32 * midi_volume) >> 9;
+        let carrier_level = MAX_OPERATOR_LEVEL - full_volume as u8;
+
+        let modulator = modulator_offset(index);
+        let settings = voice.settings;
+        self.opl
+            .write(0x40 + modulator + 3, carrier_level | settings.carrier.scale);
+        // In additive mode the modulator must not be louder than the carrier
+        if settings.feedback & 0x01 != 0 && settings.modulator.level != MAX_OPERATOR_LEVEL {
+            let level = settings.modulator.level.max(carrier_level);
+            self.opl
+                .write(0x40 + modulator, level | settings.modulator.scale);
+        }
+    }
+
+    /// Sets the frequency of a voice from its note and the pitch bend, and keys it on
+    fn update_voice_frequency(&mut self, index: usize) {
+        let voice = match self.voices[index] {
+            Some(voice) => voice,
+            None => return,
+        };
+        let frequency = voice_frequency(&voice, self.channels[voice.channel as usize].bend);
+        self.voices[index] = Some(Voice { frequency, ..voice });
+
+        self.opl.write(0xA0 + index as u8, (frequency & 0xFF) as u8);
+        self.opl
+            .write(0xB0 + index as u8, (frequency >> 8) as u8 | 0x20);
+    }
+
+    /// Releases a note, letting its envelope fade out
+    fn release_voice(&mut self, index: usize) {
+        if let Some(voice) = self.voices[index].take() {
+            self.opl
+                .write(0xB0 + index as u8, (voice.frequency >> 8) as u8);
+        }
+    }
+
+    fn release_note(&mut self, channel: u8, key: u8) {
+        for index in 0..OPL_NUM_CHANNELS {
+            if self.voices[index].is_some_and(|voice| voice.channel == channel && voice.key == key)
+            {
+                self.release_voice(index);
+            }
+        }
+    }
+
+    fn release_channel(&mut self, channel: u8) {
+        for index in 0..OPL_NUM_CHANNELS {
+            if self.voices[index].is_some_and(|voice| voice.channel == channel) {
+                self.release_voice(index);
+            }
+        }
+    }
+
+    fn release_all(&mut self) {
+        (0..OPL_NUM_CHANNELS).for_each(|index| self.release_voice(index));
+    }
+}
+
+/// Gets the frequency number and block of registers 0xA0 and 0xB0 for a voice, like Chocolate
+/// Doom's FrequencyForVoice
+/// # Arguments
+/// * `voice` - The voice, with the note it plays
+/// * `bend` - The pitch bend of its channel in 1/32 semitones
+/// # Remarks
+/// The notes below `FREQUENCY_OCTAVE_START` come straight from the start of the frequency table,
+/// the others repeat its last octave with a higher block. Like DMX, notes past block 7 are played
+/// in block 7.
+fn voice_frequency(voice: &Voice, bend: i32) -> u16 {
+    let mut note = voice.note as i32;
+    if !voice.fixed {
+        note += voice.settings.base_note_offset as i32;
+    }
+    while note < 0 {
+        note += 12;
+    }
+    while note > 95 {
+        note -= 12;
+    }
+
+    let index = (64 + 32 * note + bend + voice.fine_tuning).max(0) as usize;
+    if index < FREQUENCY_OCTAVE_START {
+        return frequency_curve(index);
+    }
+    let octave = ((index - FREQUENCY_OCTAVE_START) / FREQUENCY_OCTAVE).min(7) as u16;
+    frequency_curve(FREQUENCY_OCTAVE_START + (index - FREQUENCY_OCTAVE_START) % FREQUENCY_OCTAVE)
+        | (octave << 10)
+}
+
+/// Gets an entry of the frequency table of the DMX library
+/// # Arguments
+/// * `index` - The pitch in 1/32 semitones, starting two semitones below note 0
+/// # Returns
+/// The frequency number in block 0
+/// # Remarks
+/// The entries are the equal tempered frequencies with note 57 tuned to 440 Hz, rounded down,
+/// which gives the values of Chocolate Doom's frequency_curve table. Note 0 of the DMX library
+/// is C0, an octave above MIDI note 0, so the songs sound an octave higher than General MIDI;
+/// the base note offsets of the GENMIDI instruments make up for it.
+fn frequency_curve(index: usize) -> u16 {
+    let semitones = index as f64 / 32.0 - 2.0;
+    let hertz = 440.0 * 2f64.powf((semitones - 57.0) / 12.0);
+    (hertz * (1u32 << 20) as f64 / OPL_RATE) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genmidi::{GenMidiInstrument, GENMIDI_FLAG_DOUBLE_VOICE, GENMIDI_NUM_INSTRUMENTS};
+
+    fn voice(note: u8, base_note_offset: i16, fixed: bool) -> Voice {
+        Voice {
+            channel: 0,
+            key: note,
+            note,
+            settings: GenMidiVoice {
+                base_note_offset,
+                ..Default::default()
+            },
+            fixed,
+            second: false,
+            fine_tuning: 0,
+            volume: 127,
+            frequency: 0,
+        }
+    }
+
+    /// The frequency in Hz that register values play
+    fn hertz(frequency: u16) -> f64 {
+        (frequency & 0x3FF) as f64 * OPL_RATE / (1u32 << (20 - (frequency >> 10))) as f64
+    }
+
+    #[test]
+    fn the_frequency_table_matches_vanilla() {
+        // The first rows of frequency_curve in Chocolate Doom's i_oplmusic.c
+        let vanilla: [u16; 24] = [
+            0x133, 0x133, 0x134, 0x134, 0x135, 0x136, 0x136, 0x137, 0x137, 0x138, 0x138, 0x139,
+            0x139, 0x13a, 0x13b, 0x13b, 0x13c, 0x13c, 0x13d, 0x13d, 0x13e, 0x13f, 0x13f, 0x140,
+        ];
+        let table: Vec<u16> = (0..vanilla.len()).map(frequency_curve).collect();
+        assert_eq!(table, vanilla);
+        // The octave that is repeated fits in the 10 bits of the frequency number
+        assert!(frequency_curve(FREQUENCY_OCTAVE_START) >= 0x200);
+        assert!(frequency_curve(FREQUENCY_OCTAVE_START + FREQUENCY_OCTAVE - 2) < 0x400);
+    }
+
+    #[test]
+    fn notes_play_an_octave_above_general_midi() {
+        // MIDI note 69 is A4 at 440 Hz, DMX plays it as A5
+        let frequency = voice_frequency(&voice(69, 0, false), 0);
+        assert_eq!(frequency >> 10, 5);
+        assert!((hertz(frequency) - 880.0).abs() < 1.0);
+        // The base note offset of the instrument moves it back down
+        let frequency = voice_frequency(&voice(69, -12, false), 0);
+        assert_eq!(frequency >> 10, 4);
+        assert!((hertz(frequency) - 440.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn fixed_notes_ignore_the_base_note_offset() {
+        assert_eq!(
+            voice_frequency(&voice(60, -12, true), 0),
+            voice_frequency(&voice(60, 0, false), 0)
+        );
+    }
+
+    #[test]
+    fn bends_and_fine_tuning_move_through_the_table() {
+        // A full bend down from note 0 reaches the start of the table
+        assert_eq!(voice_frequency(&voice(0, 0, false), -64), 0x133);
+        // A bend of a semitone is the next note
+        assert_eq!(
+            voice_frequency(&voice(60, 0, false), 32),
+            voice_frequency(&voice(61, 0, false), 0)
+        );
+        let second = Voice {
+            fine_tuning: 32,
+            ..voice(60, 0, false)
+        };
+        assert_eq!(
+            voice_frequency(&second, 0),
+            voice_frequency(&voice(61, 0, false), 0)
+        );
+    }
+
+    #[test]
+    fn notes_out_of_range_wrap_by_octaves() {
+        assert_eq!(
+            // Note -7 is played as note 5
+            voice_frequency(&voice(5, -12, false), 0),
+            voice_frequency(&voice(5, 0, false), 0)
+        );
+        assert_eq!(
+            voice_frequency(&voice(127, 0, false), 0),
+            voice_frequency(&voice(91, 0, false), 0)
+        );
+        // The highest notes stay in block 7
+        assert_eq!(voice_frequency(&voice(95, 0, false), 63) >> 10, 7);
+    }
+
+    /// A player of an empty song, where program 1 is a double voice instrument
+    fn player() -> MusicPlayer {
+        let mut instruments = vec![
+            GenMidiInstrument {
+                name: String::new(),
+                flags: 0,
+                fine_tuning: 128,
+                fixed_note: 0,
+                voices: Default::default(),
+            };
+            GENMIDI_NUM_INSTRUMENTS
+        ];
+        instruments[1].flags = GENMIDI_FLAG_DOUBLE_VOICE;
+        let song = Mus {
+            primary_channels: 0,
+            secondary_channels: 0,
+            instruments: Vec::new(),
+            events: Vec::new(),
+        };
+        MusicPlayer::new(
+            Arc::new(song),
+            Arc::new(GenMidi { instruments }),
+            11025,
+            false,
+        )
+    }
+
+    fn playing_channels(player: &MusicPlayer) -> Vec<(u8, bool)> {
+        player
+            .voices
+            .iter()
+            .flatten()
+            .map(|voice| (voice.channel, voice.second))
+            .collect()
+    }
+
+    #[test]
+    fn double_voice_instruments_take_two_channels_while_there_is_room() {
+        let mut player = player();
+        player.play_event(MusEvent::Controller {
+            channel: 2,
+            controller: MUS_CONTROLLER_INSTRUMENT,
+            value: 1,
+        });
+        player.play_note(2, 60, 127);
+        assert_eq!(playing_channels(&player), [(2, false), (2, true)]);
+        assert_eq!(
+            player.voices[0].unwrap().frequency,
+            voice_frequency(&voice(60, 0, false), 0)
+        );
+
+        // When all channels are taken, the second voices go first, then the highest channels
+        for channel in 3..10 {
+            player.play_note(channel, 60, 127);
+        }
+        assert_eq!(playing_channels(&player).len(), OPL_NUM_CHANNELS);
+        player.play_note(0, 60, 127);
+        assert!(playing_channels(&player).iter().all(|&(_, second)| !second));
+        player.play_note(1, 60, 127);
+        assert!(!playing_channels(&player).contains(&(9, false)));
+
+        player.play_event(MusEvent::ReleaseNote {
+            channel: 2,
+            note: 60,
+        });
+        assert!(!playing_channels(&player).contains(&(2, false)));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::audio::Sound;
+use crate::wad::{LumpReader, WadError};
+use std::time::Duration;
+
+/// The format number of PC speaker sound lumps
+const PC_SPEAKER_FORMAT: u16 = 0;
+/// The amount of tones per second
+pub const PC_SPEAKER_TONE_RATE: u32 = 140;
+/// The frequency of the timer chip that drives the speaker, in Hz
+const PIT_FREQUENCY: f64 = 1_193_181.0;
+/// The sample rate the tones are synthesized at, high enough for the highest tone
+pub const PC_SPEAKER_SAMPLE_RATE: u32 = 44100;
+/// How far the square wave swings from silence, in unsigned 8-bit samples
+const SQUARE_WAVE_AMPLITUDE: u8 = 64;
+
+/// The timer divisors of the tones, from Chocolate Doom's i_pcsound.c; tone 0 is silence
+const DIVISORS: [u16; 128] = [
+    0, 6818, 6628, 6449, 6279, 6087, 5906, 5736, 5575, 5423, 5279, 5120, 4971, 4830, 4697, 4554,
+    4435, 4307, 4186, 4058, 3950, 3836, 3728, 3615, 3519, 3418, 3323, 3224, 3131, 3043, 2960, 2875,
+    2794, 2711, 2633, 2560, 2485, 2415, 2348, 2281, 2213, 2153, 2089, 2032, 1975, 1918, 1864, 1810,
+    1757, 1709, 1659, 1612, 1565, 1521, 1478, 1435, 1395, 1355, 1316, 1280, 1242, 1207, 1173, 1140,
+    1107, 1075, 1045, 1015, 986, 959, 931, 905, 879, 854, 829, 806, 783, 760, 739, 718, 697, 677,
+    658, 640, 621, 604, 586, 570, 553, 538, 522, 507, 493, 479, 465, 452, 439, 427, 415, 403, 391,
+    380, 369, 359, 348, 339, 329, 319, 310, 302, 293, 285, 276, 269, 261, 253, 246, 239, 232, 226,
+    219, 213, 207, 201, 195, 190, 184, 179,
+];
+
+/// A PC speaker sound effect, e.g. `DPPISTOL`
+/// # Remarks
+/// The PC speaker can only play a square wave at a single frequency, so these sounds are a list
+/// of tones, each lasting 1/140th of a second. A tone is an index into the table of timer
+/// divisors the DMX library used.
+/// # Examples
+/// ```
+/// use doom::pc_speaker::PcSound;
+/// // Three tones: two of them audible, then silence
+/// let sound = PcSound::decode("DPTEST", &[0, 0, 3, 0, 60, 61, 0]).unwrap();
+/// assert_eq!(sound.tones.len(), 3);
+/// assert_eq!(sound.to_sound().samples.len(), 3 * 315);
+/// ```
+pub struct PcSound {
+    pub tones: Vec<u8>,
+}
+
+impl PcSound {
+    /// Decodes a PC speaker sound lump
+    /// # Returns
+    /// The sound, an `UnsupportedFormat` error if the lump isn't a PC speaker sound, or a
+    /// `TruncatedLump` error if the lump holds fewer tones than its header says
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self, WadError> {
+        let mut reader = LumpReader::new(name, data);
+        let format = reader.read_u16()?;
+        if format != PC_SPEAKER_FORMAT {
+            return Err(WadError::UnsupportedFormat {
+                name: name.to_string(),
+                offset: 0,
+                reason: format!("sound format {} is not a PC speaker sound", format),
+            });
+        }
+        let count = reader.read_u16()? as usize;
+        Ok(Self {
+            tones: reader.read_bytes(count)?.to_vec(),
+        })
+    }
+
+    /// The length of the sound
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.tones.len() as f64 / PC_SPEAKER_TONE_RATE as f64)
+    }
+
+    /// Gets the frequency of a tone in Hz, or `None` for silence
+    /// # Remarks
+    /// Tones outside the table are silent as well.
+    pub fn frequency(tone: u8) -> Option<f64> {
+        match DIVISORS.get(tone as usize) {
+            Some(&divisor) if divisor > 0 => Some(PIT_FREQUENCY / divisor as f64),
+            _ => None,
+        }
+    }
+
+    /// Synthesizes the square wave the PC speaker would play
+    /// # Returns
+    /// A sound at `PC_SPEAKER_SAMPLE_RATE`, which the mixer plays like a digitized sound
+    /// # Remarks
+    /// The wave keeps its phase from one tone to the next, so tone changes don't click.
+    pub fn to_sound(&self) -> Sound {
+        let samples_per_tone = (PC_SPEAKER_SAMPLE_RATE / PC_SPEAKER_TONE_RATE) as usize;
+        let mut samples = Vec::with_capacity(self.tones.len() * samples_per_tone);
+        let mut phase = 0.0;
+        for &tone in &self.tones {
+            let frequency = match Self::frequency(tone) {
+                Some(frequency) => frequency,
+                None => {
+                    samples.resize(samples.len() + samples_per_tone, 128);
+                    continue;
+                }
+            };
+            let step = frequency / PC_SPEAKER_SAMPLE_RATE as f64;
+            samples.extend((0..samples_per_tone).map(|_| {
+                let sample = match phase < 0.5 {
+                    true => 128 + SQUARE_WAVE_AMPLITUDE,
+                    false => 128 - SQUARE_WAVE_AMPLITUDE,
+                };
+                phase = (phase + step).fract();
+                sample
+            }));
+        }
+
+        Sound {
+            sample_rate: PC_SPEAKER_SAMPLE_RATE,
+            samples,
+        }
+    }
+}
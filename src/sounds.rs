@@ -0,0 +1,552 @@
+/// The sound effects, in the order of vanilla's sfxenum_t
+/// # Remarks
+/// The number of a sound is its index in the sound table, as used by DEHACKED patches. Sound 0
+/// is a dummy that plays nothing.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum SfxId {
+    None,
+    Pistol,
+    Shotgn,
+    Sgcock,
+    Dshtgn,
+    Dbopn,
+    Dbcls,
+    Dbload,
+    Plasma,
+    Bfg,
+    Sawup,
+    Sawidl,
+    Sawful,
+    Sawhit,
+    Rlaunc,
+    Rxplod,
+    Firsht,
+    Firxpl,
+    Pstart,
+    Pstop,
+    Doropn,
+    Dorcls,
+    Stnmov,
+    Swtchn,
+    Swtchx,
+    Plpain,
+    Dmpain,
+    Popain,
+    Vipain,
+    Mnpain,
+    Pepain,
+    Slop,
+    Itemup,
+    Wpnup,
+    Oof,
+    Telept,
+    Posit1,
+    Posit2,
+    Posit3,
+    Bgsit1,
+    Bgsit2,
+    Sgtsit,
+    Cacsit,
+    Brssit,
+    Cybsit,
+    Spisit,
+    Bspsit,
+    Kntsit,
+    Vilsit,
+    Mansit,
+    Pesit,
+    Sklatk,
+    Sgtatk,
+    Skepch,
+    Vilatk,
+    Claw,
+    Skeswg,
+    Pldeth,
+    Pdiehi,
+    Podth1,
+    Podth2,
+    Podth3,
+    Bgdth1,
+    Bgdth2,
+    Sgtdth,
+    Cacdth,
+    Skldth,
+    Brsdth,
+    Cybdth,
+    Spidth,
+    Bspdth,
+    Vildth,
+    Kntdth,
+    Pedth,
+    Skedth,
+    Posact,
+    Bgact,
+    Dmact,
+    Bspact,
+    Bspwlk,
+    Vilact,
+    Noway,
+    Barexp,
+    Punch,
+    Hoof,
+    Metal,
+    Chgun,
+    Tink,
+    Bdopn,
+    Bdcls,
+    Itmbk,
+    Flame,
+    Flamst,
+    Getpow,
+    Bospit,
+    Boscub,
+    Bossit,
+    Bospn,
+    Bosdth,
+    Manatk,
+    Mandth,
+    Sssit,
+    Ssdth,
+    Keenpn,
+    Keendt,
+    Skeact,
+    Skesit,
+    Skeatk,
+    Radio,
+}
+
+/// The amount of sound effects, including the dummy sound 0
+pub const NUM_SFX: usize = 109;
+
+impl SfxId {
+    /// All sound effects, in table order
+    pub const ALL: [SfxId; NUM_SFX] = [
+        SfxId::None,
+        SfxId::Pistol,
+        SfxId::Shotgn,
+        SfxId::Sgcock,
+        SfxId::Dshtgn,
+        SfxId::Dbopn,
+        SfxId::Dbcls,
+        SfxId::Dbload,
+        SfxId::Plasma,
+        SfxId::Bfg,
+        SfxId::Sawup,
+        SfxId::Sawidl,
+        SfxId::Sawful,
+        SfxId::Sawhit,
+        SfxId::Rlaunc,
+        SfxId::Rxplod,
+        SfxId::Firsht,
+        SfxId::Firxpl,
+        SfxId::Pstart,
+        SfxId::Pstop,
+        SfxId::Doropn,
+        SfxId::Dorcls,
+        SfxId::Stnmov,
+        SfxId::Swtchn,
+        SfxId::Swtchx,
+        SfxId::Plpain,
+        SfxId::Dmpain,
+        SfxId::Popain,
+        SfxId::Vipain,
+        SfxId::Mnpain,
+        SfxId::Pepain,
+        SfxId::Slop,
+        SfxId::Itemup,
+        SfxId::Wpnup,
+        SfxId::Oof,
+        SfxId::Telept,
+        SfxId::Posit1,
+        SfxId::Posit2,
+        SfxId::Posit3,
+        SfxId::Bgsit1,
+        SfxId::Bgsit2,
+        SfxId::Sgtsit,
+        SfxId::Cacsit,
+        SfxId::Brssit,
+        SfxId::Cybsit,
+        SfxId::Spisit,
+        SfxId::Bspsit,
+        SfxId::Kntsit,
+        SfxId::Vilsit,
+        SfxId::Mansit,
+        SfxId::Pesit,
+        SfxId::Sklatk,
+        SfxId::Sgtatk,
+        SfxId::Skepch,
+        SfxId::Vilatk,
+        SfxId::Claw,
+        SfxId::Skeswg,
+        SfxId::Pldeth,
+        SfxId::Pdiehi,
+        SfxId::Podth1,
+        SfxId::Podth2,
+        SfxId::Podth3,
+        SfxId::Bgdth1,
+        SfxId::Bgdth2,
+        SfxId::Sgtdth,
+        SfxId::Cacdth,
+        SfxId::Skldth,
+        SfxId::Brsdth,
+        SfxId::Cybdth,
+        SfxId::Spidth,
+        SfxId::Bspdth,
+        SfxId::Vildth,
+        SfxId::Kntdth,
+        SfxId::Pedth,
+        SfxId::Skedth,
+        SfxId::Posact,
+        SfxId::Bgact,
+        SfxId::Dmact,
+        SfxId::Bspact,
+        SfxId::Bspwlk,
+        SfxId::Vilact,
+        SfxId::Noway,
+        SfxId::Barexp,
+        SfxId::Punch,
+        SfxId::Hoof,
+        SfxId::Metal,
+        SfxId::Chgun,
+        SfxId::Tink,
+        SfxId::Bdopn,
+        SfxId::Bdcls,
+        SfxId::Itmbk,
+        SfxId::Flame,
+        SfxId::Flamst,
+        SfxId::Getpow,
+        SfxId::Bospit,
+        SfxId::Boscub,
+        SfxId::Bossit,
+        SfxId::Bospn,
+        SfxId::Bosdth,
+        SfxId::Manatk,
+        SfxId::Mandth,
+        SfxId::Sssit,
+        SfxId::Ssdth,
+        SfxId::Keenpn,
+        SfxId::Keendt,
+        SfxId::Skeact,
+        SfxId::Skesit,
+        SfxId::Skeatk,
+        SfxId::Radio,
+    ];
+
+    /// Gets a sound effect by its number, or `None` if there is no such sound
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+/// How a sound effect is played, like vanilla's sfxinfo_t
+#[derive(Debug, PartialEq, Clone)]
+pub struct SfxInfo {
+    /// The name of the lumps without the `DS` or `DP` prefix, e.g. `PISTOL`
+    pub name: String,
+    /// Whether the sound should only play once at a time; vanilla never checks it
+    pub singularity: bool,
+    /// The priority of the sound, a lower number is more important
+    pub priority: i32,
+    /// Another sound whose lumps are played instead, like the chaingun playing the pistol sound
+    pub link: Option<SfxId>,
+    /// The pitch of a linked sound, or -1; the mixer plays every sound at its own pitch, like
+    /// vanilla 1.9
+    pub pitch: i32,
+    /// Added to the volume of a linked sound, in sound menu steps, or -1
+    pub volume: i32,
+}
+
+/// A sound of the table as it plays, with its link followed
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResolvedSfx<'a> {
+    /// The name of the lumps to play
+    pub name: &'a str,
+    /// The priority of the sound itself, not of the link
+    pub priority: i32,
+    /// The volume adjustment of a linked sound, 0 for sounds without a link
+    pub volume: i32,
+}
+
+/// A sound of vanilla's table, before any remapping
+struct VanillaSfx {
+    name: &'static str,
+    singularity: bool,
+    priority: i32,
+    link: Option<SfxId>,
+    pitch: i32,
+    volume: i32,
+}
+
+const fn sfx(
+    name: &'static str,
+    singularity: bool,
+    priority: i32,
+    link: Option<SfxId>,
+    pitch: i32,
+    volume: i32,
+) -> VanillaSfx {
+    VanillaSfx {
+        name,
+        singularity,
+        priority,
+        link,
+        pitch,
+        volume,
+    }
+}
+
+/// The sound table of vanilla's sounds.c, in `SfxId` order
+const VANILLA_SFX: [VanillaSfx; NUM_SFX] = [
+    sfx("NONE", false, 0, None, -1, -1),
+    sfx("PISTOL", false, 64, None, -1, -1),
+    sfx("SHOTGN", false, 64, None, -1, -1),
+    sfx("SGCOCK", false, 64, None, -1, -1),
+    sfx("DSHTGN", false, 64, None, -1, -1),
+    sfx("DBOPN", false, 64, None, -1, -1),
+    sfx("DBCLS", false, 64, None, -1, -1),
+    sfx("DBLOAD", false, 64, None, -1, -1),
+    sfx("PLASMA", false, 64, None, -1, -1),
+    sfx("BFG", false, 64, None, -1, -1),
+    sfx("SAWUP", false, 64, None, -1, -1),
+    sfx("SAWIDL", false, 118, None, -1, -1),
+    sfx("SAWFUL", false, 64, None, -1, -1),
+    sfx("SAWHIT", false, 64, None, -1, -1),
+    sfx("RLAUNC", false, 64, None, -1, -1),
+    sfx("RXPLOD", false, 70, None, -1, -1),
+    sfx("FIRSHT", false, 70, None, -1, -1),
+    sfx("FIRXPL", false, 70, None, -1, -1),
+    sfx("PSTART", false, 100, None, -1, -1),
+    sfx("PSTOP", false, 100, None, -1, -1),
+    sfx("DOROPN", false, 100, None, -1, -1),
+    sfx("DORCLS", false, 100, None, -1, -1),
+    sfx("STNMOV", false, 119, None, -1, -1),
+    sfx("SWTCHN", false, 78, None, -1, -1),
+    sfx("SWTCHX", false, 78, None, -1, -1),
+    sfx("PLPAIN", false, 96, None, -1, -1),
+    sfx("DMPAIN", false, 96, None, -1, -1),
+    sfx("POPAIN", false, 96, None, -1, -1),
+    sfx("VIPAIN", false, 96, None, -1, -1),
+    sfx("MNPAIN", false, 96, None, -1, -1),
+    sfx("PEPAIN", false, 96, None, -1, -1),
+    sfx("SLOP", false, 78, None, -1, -1),
+    sfx("ITEMUP", true, 78, None, -1, -1),
+    sfx("WPNUP", true, 78, None, -1, -1),
+    sfx("OOF", false, 96, None, -1, -1),
+    sfx("TELEPT", false, 32, None, -1, -1),
+    sfx("POSIT1", true, 98, None, -1, -1),
+    sfx("POSIT2", true, 98, None, -1, -1),
+    sfx("POSIT3", true, 98, None, -1, -1),
+    sfx("BGSIT1", true, 98, None, -1, -1),
+    sfx("BGSIT2", true, 98, None, -1, -1),
+    sfx("SGTSIT", true, 98, None, -1, -1),
+    sfx("CACSIT", true, 98, None, -1, -1),
+    sfx("BRSSIT", true, 94, None, -1, -1),
+    sfx("CYBSIT", true, 92, None, -1, -1),
+    sfx("SPISIT", true, 90, None, -1, -1),
+    sfx("BSPSIT", true, 90, None, -1, -1),
+    sfx("KNTSIT", true, 90, None, -1, -1),
+    sfx("VILSIT", true, 90, None, -1, -1),
+    sfx("MANSIT", true, 90, None, -1, -1),
+    sfx("PESIT", true, 90, None, -1, -1),
+    sfx("SKLATK", false, 70, None, -1, -1),
+    sfx("SGTATK", false, 70, None, -1, -1),
+    sfx("SKEPCH", false, 70, None, -1, -1),
+    sfx("VILATK", false, 70, None, -1, -1),
+    sfx("CLAW", false, 70, None, -1, -1),
+    sfx("SKESWG", false, 70, None, -1, -1),
+    sfx("PLDETH", false, 32, None, -1, -1),
+    sfx("PDIEHI", false, 32, None, -1, -1),
+    sfx("PODTH1", false, 70, None, -1, -1),
+    sfx("PODTH2", false, 70, None, -1, -1),
+    sfx("PODTH3", false, 70, None, -1, -1),
+    sfx("BGDTH1", false, 70, None, -1, -1),
+    sfx("BGDTH2", false, 70, None, -1, -1),
+    sfx("SGTDTH", false, 70, None, -1, -1),
+    sfx("CACDTH", false, 70, None, -1, -1),
+    sfx("SKLDTH", false, 70, None, -1, -1),
+    sfx("BRSDTH", false, 32, None, -1, -1),
+    sfx("CYBDTH", false, 32, None, -1, -1),
+    sfx("SPIDTH", false, 32, None, -1, -1),
+    sfx("BSPDTH", false, 32, None, -1, -1),
+    sfx("VILDTH", false, 32, None, -1, -1),
+    sfx("KNTDTH", false, 32, None, -1, -1),
+    sfx("PEDTH", false, 32, None, -1, -1),
+    sfx("SKEDTH", false, 32, None, -1, -1),
+    sfx("POSACT", true, 120, None, -1, -1),
+    sfx("BGACT", true, 120, None, -1, -1),
+    sfx("DMACT", true, 120, None, -1, -1),
+    sfx("BSPACT", true, 100, None, -1, -1),
+    sfx("BSPWLK", true, 100, None, -1, -1),
+    sfx("VILACT", true, 100, None, -1, -1),
+    sfx("NOWAY", false, 78, None, -1, -1),
+    sfx("BAREXP", false, 60, None, -1, -1),
+    sfx("PUNCH", false, 64, None, -1, -1),
+    sfx("HOOF", false, 70, None, -1, -1),
+    sfx("METAL", false, 70, None, -1, -1),
+    sfx("CHGUN", false, 64, Some(SfxId::Pistol), 150, 0),
+    sfx("TINK", false, 60, None, -1, -1),
+    sfx("BDOPN", false, 100, None, -1, -1),
+    sfx("BDCLS", false, 100, None, -1, -1),
+    sfx("ITMBK", false, 100, None, -1, -1),
+    sfx("FLAME", false, 32, None, -1, -1),
+    sfx("FLAMST", false, 32, None, -1, -1),
+    sfx("GETPOW", false, 60, None, -1, -1),
+    sfx("BOSPIT", false, 70, None, -1, -1),
+    sfx("BOSCUB", false, 70, None, -1, -1),
+    sfx("BOSSIT", false, 70, None, -1, -1),
+    sfx("BOSPN", false, 70, None, -1, -1),
+    sfx("BOSDTH", false, 70, None, -1, -1),
+    sfx("MANATK", false, 70, None, -1, -1),
+    sfx("MANDTH", false, 70, None, -1, -1),
+    sfx("SSSIT", false, 70, None, -1, -1),
+    sfx("SSDTH", false, 70, None, -1, -1),
+    sfx("KEENPN", false, 70, None, -1, -1),
+    sfx("KEENDT", false, 70, None, -1, -1),
+    sfx("SKEACT", false, 70, None, -1, -1),
+    sfx("SKESIT", false, 70, None, -1, -1),
+    sfx("SKEATK", false, 70, None, -1, -1),
+    sfx("RADIO", false, 60, None, -1, -1),
+];
+
+/// The sound effects and how they are played
+/// # Remarks
+/// The table starts out as vanilla's. Game code refers to sounds by `SfxId`, so a DEHACKED
+/// patch can point a sound to other lumps or change its priority without touching the code,
+/// while a PWAD replaces a sound by simply containing a lump of the same name.
+/// # Examples
+/// ```
+/// use doom::sounds::{SfxId, SfxTable};
+/// let mut table = SfxTable::default();
+/// assert_eq!(table.resolve(SfxId::Chgun).name, "PISTOL");
+/// table.remap(SfxId::Pistol, "DSHTGN");
+/// assert_eq!(table.resolve(SfxId::Pistol).name, "DSHTGN");
+/// ```
+pub struct SfxTable {
+    entries: Vec<SfxInfo>,
+}
+
+impl Default for SfxTable {
+    fn default() -> Self {
+        Self {
+            entries: VANILLA_SFX
+                .iter()
+                .map(|vanilla| SfxInfo {
+                    name: vanilla.name.to_string(),
+                    singularity: vanilla.singularity,
+                    priority: vanilla.priority,
+                    link: vanilla.link,
+                    pitch: vanilla.pitch,
+                    volume: vanilla.volume,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SfxTable {
+    /// Gets how a sound is played, without following its link
+    pub fn get(&self, id: SfxId) -> &SfxInfo {
+        &self.entries[id as usize]
+    }
+
+    /// Gets a sound to change how it is played, e.g. from a DEHACKED patch
+    pub fn get_mut(&mut self, id: SfxId) -> &mut SfxInfo {
+        &mut self.entries[id as usize]
+    }
+
+    /// Makes a sound play other lumps
+    /// # Arguments
+    /// * `id` - The sound to change
+    /// * `name` - The name of the lumps without the `DS` or `DP` prefix
+    pub fn remap(&mut self, id: SfxId, name: &str) {
+        self.get_mut(id).name = name.to_ascii_uppercase();
+    }
+
+    /// Finds a sound by the name of its lumps, without the `DS` or `DP` prefix
+    pub fn find(&self, name: &str) -> Option<SfxId> {
+        self.entries
+            .iter()
+            .position(|info| info.name.eq_ignore_ascii_case(name))
+            .and_then(SfxId::from_index)
+    }
+
+    /// Follows the link of a sound, like vanilla's S_StartSound
+    /// # Remarks
+    /// A linked sound plays the lumps of its link with its own priority. Links are followed
+    /// once, as in vanilla.
+    pub fn resolve(&self, id: SfxId) -> ResolvedSfx<'_> {
+        let info = self.get(id);
+        match info.link {
+            Some(link) => ResolvedSfx {
+                name: &self.get(link).name,
+                priority: info.priority,
+                volume: info.volume,
+            },
+            None => ResolvedSfx {
+                name: &info.name,
+                priority: info.priority,
+                volume: 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_follow_the_vanilla_table() {
+        let table = SfxTable::default();
+        assert_eq!(SfxId::from_index(NUM_SFX - 1), Some(SfxId::Radio));
+        assert_eq!(SfxId::from_index(NUM_SFX), None);
+        assert!(SfxId::ALL
+            .iter()
+            .enumerate()
+            .all(|(index, &id)| id as usize == index));
+        assert_eq!(table.get(SfxId::Radio).name, "RADIO");
+        assert_eq!(table.get(SfxId::Sawidl).priority, 118);
+    }
+
+    #[test]
+    fn resolve_plays_the_link_with_its_own_priority() {
+        let mut table = SfxTable::default();
+        table.get_mut(SfxId::Chgun).priority = 10;
+        let chgun = table.get(SfxId::Chgun);
+        assert_eq!((chgun.pitch, chgun.volume), (150, 0));
+        assert_eq!(
+            table.resolve(SfxId::Chgun),
+            ResolvedSfx {
+                name: "PISTOL",
+                priority: 10,
+                volume: 0,
+            }
+        );
+        assert_eq!(
+            table.resolve(SfxId::Oof),
+            ResolvedSfx {
+                name: "OOF",
+                priority: 96,
+                volume: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn find_ignores_case() {
+        let table = SfxTable::default();
+        assert_eq!(table.find("pistol"), Some(SfxId::Pistol));
+        assert_eq!(table.find("DSPISTOL"), None);
+    }
+
+    #[test]
+    fn remap_changes_the_lumps_of_linked_sounds_too() {
+        let mut table = SfxTable::default();
+        table.remap(SfxId::Pistol, "dshtgn");
+        assert_eq!(table.resolve(SfxId::Pistol).name, "DSHTGN");
+        assert_eq!(table.resolve(SfxId::Chgun).name, "DSHTGN");
+        // The first sound with the name is found
+        assert_eq!(table.find("DSHTGN"), Some(SfxId::Pistol));
+        assert_eq!(table.find("PISTOL"), None);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::wad::{WadError, WadFile, SPRITES_END, SPRITES_START};
+use std::collections::HashMap;
+
+/// The amount of angles a rotating sprite frame is drawn from
+pub const NUM_ROTATIONS: usize = 8;
+
+/// A single frame of a sprite, e.g. `TROOA` for the first frame of the imp
+/// # Remarks
+/// Rotations are numbered 1 to 8 like in the lump names: 1 faces the viewer and they go
+/// counterclockwise in steps of 45 degrees. Frames without rotations use the same lump for every
+/// angle.
+#[derive(Debug, Clone, Default)]
+pub struct SpriteFrame {
+    /// Whether the frame has a lump per rotation, or one lump (rotation 0) for all of them
+    pub rotate: bool,
+    /// The directory index of the lump for each rotation
+    pub lumps: [Option<usize>; NUM_ROTATIONS],
+    /// Whether the lump for each rotation is drawn mirrored
+    pub flip: [bool; NUM_ROTATIONS],
+}
+
+impl SpriteFrame {
+    /// Returns true if there is a lump for every rotation
+    pub fn is_complete(&self) -> bool {
+        self.lumps.iter().all(|lump| lump.is_some())
+    }
+}
+
+/// All frames of a sprite, e.g. `TROO` for the imp
+#[derive(Debug, Clone)]
+pub struct SpriteDef {
+    pub name: String,
+    /// The frames by frame number, `A` is 0; frames that have no lumps are `None`
+    pub frames: Vec<Option<SpriteFrame>>,
+}
+
+/// The sprites of a WAD file, grouped by name, frame and rotation
+/// # Remarks
+/// This is built from the lumps between `S_START` and `S_END`, which are named after the
+/// 4-letter sprite name, a frame letter and a rotation digit, e.g. `POSSC3`. A lump can be used
+/// for two rotations by appending a second frame letter and rotation digit, like `TROOA2A8`: the
+/// second rotation draws the lump mirrored. Like vanilla's R_InitSpriteDefs, rotation 0 means the
+/// lump is used for every angle.
+pub struct SpriteCatalog {
+    sprites: HashMap<String, SpriteDef>,
+    /// The errors of the lumps that were skipped
+    errors: Vec<WadError>,
+}
+
+impl SpriteCatalog {
+    /// Groups the lumps of the sprite namespace
+    /// # Remarks
+    /// A lump whose name doesn't follow the convention is skipped rather than failing the whole
+    /// catalog, since it may come from a PWAD; see `errors`.
+    /// # Examples
+    /// ```no_run
+    /// use doom::sprite::SpriteCatalog;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let sprites = SpriteCatalog::load(&wad);
+    /// // The former human, third frame, seen from the left
+    /// let (lump, flip) = sprites.lookup("POSS", 'C', 3).unwrap();
+    /// let picture = wad.get_image(&wad.get_lump_by_index(lump).unwrap().name).unwrap();
+    /// ```
+    pub fn load(wad: &WadFile) -> Self {
+        let mut sprites: HashMap<String, SpriteDef> = HashMap::new();
+        let mut errors = Vec::new();
+        let range = match wad.get_namespace_range(SPRITES_START, SPRITES_END) {
+            Some(range) => range,
+            None => return Self { sprites, errors },
+        };
+
+        for index in range {
+            let lump = &wad.lumps[index];
+            // Markers and other empty lumps are not sprites
+            if lump.size() == 0 {
+                continue;
+            }
+            let rotations = match parse_lump_name(&lump.name) {
+                Some(rotations) => rotations,
+                None => {
+                    errors.push(bad_sprite_name(&lump.name));
+                    continue;
+                }
+            };
+
+            let sprite = sprites
+                .entry(lump.name[..4].to_string())
+                .or_insert_with(|| SpriteDef {
+                    name: lump.name[..4].to_string(),
+                    frames: Vec::new(),
+                });
+            // Only the second frame and rotation of a name is mirrored
+            for (i, &(frame, rotation)) in rotations.iter().enumerate() {
+                install_lump(sprite, frame, rotation, index, i == 1);
+            }
+        }
+
+        Self { sprites, errors }
+    }
+
+    /// Gets the errors of the lumps that were skipped because of their names
+    pub fn errors(&self) -> &[WadError] {
+        &self.errors
+    }
+
+    /// Gets a sprite by its 4-letter name
+    pub fn get(&self, name: &str) -> Option<&SpriteDef> {
+        self.sprites.get(&name.to_ascii_uppercase())
+    }
+
+    /// Gets the names of all sprites, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sprites.keys().map(|name| name.as_str())
+    }
+
+    /// Looks up the lump to draw for a sprite frame seen from an angle
+    /// # Arguments
+    /// * `name` - The 4-letter sprite name, e.g. `POSS`
+    /// * `frame` - The frame letter, e.g. `C`
+    /// * `rotation` - The rotation, 1 to 8; it is ignored for frames without rotations
+    /// # Returns
+    /// The directory index of the lump and whether to draw it mirrored, or `None` if the sprite
+    /// has no such frame or rotation
+    pub fn lookup(&self, name: &str, frame: char, rotation: usize) -> Option<(usize, bool)> {
+        let frame = frame_number(frame.to_ascii_uppercase() as u8)?;
+        let frame = self.get(name)?.frames.get(frame)?.as_ref()?;
+        let rotation = match frame.rotate {
+            true => rotation.checked_sub(1).filter(|&r| r < NUM_ROTATIONS)?,
+            false => 0,
+        };
+        frame.lumps[rotation].map(|lump| (lump, frame.flip[rotation]))
+    }
+}
+
+/// Splits a sprite lump name into the frame and rotation numbers it is used for
+/// # Returns
+/// One frame and rotation, or two for a name like `TROOA2A8`, or `None` if the name is malformed
+fn parse_lump_name(name: &str) -> Option<Vec<(usize, usize)>> {
+    let bytes = name.as_bytes();
+    if !name.is_ascii() || (bytes.len() != 6 && bytes.len() != 8) {
+        return None;
+    }
+    bytes[4..]
+        .chunks(2)
+        .map(|pair| match pair[1] {
+            b'0'..=b'8' => Some((frame_number(pair[0])?, (pair[1] - b'0') as usize)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Adds a lump to a frame of a sprite, like vanilla's R_InstallSpriteLump
+/// # Remarks
+/// Vanilla quits when a rotation is given twice or a frame mixes rotation 0 with rotations 1 to
+/// 8. Since PWADs are merged into the namespace, a later lump simply replaces an earlier one
+/// here: rotation 0 replaces all rotations, and a rotation replaces a lump used for every angle.
+fn install_lump(sprite: &mut SpriteDef, frame: usize, rotation: usize, index: usize, flip: bool) {
+    if sprite.frames.len() <= frame {
+        sprite.frames.resize(frame + 1, None);
+    }
+    let frame = sprite.frames[frame].get_or_insert_with(SpriteFrame::default);
+
+    if rotation == 0 {
+        // The lump is used for every angle
+        frame.rotate = false;
+        frame.lumps = [Some(index); NUM_ROTATIONS];
+        frame.flip = [flip; NUM_ROTATIONS];
+    } else {
+        if !frame.rotate {
+            // A frame is drawn either the same from every angle or from each rotation
+            *frame = SpriteFrame {
+                rotate: true,
+                ..SpriteFrame::default()
+            };
+        }
+        frame.lumps[rotation - 1] = Some(index);
+        frame.flip[rotation - 1] = flip;
+    }
+}
+
+/// Gets the frame number for a frame letter; vanilla allows up to 29 frames, `A` to `\`
+fn frame_number(frame: u8) -> Option<usize> {
+    match frame {
+        b'A'..=b'\\' => Some((frame - b'A') as usize),
+        _ => None,
+    }
+}
+
+fn bad_sprite_name(name: &str) -> WadError {
+    WadError::BadHeader {
+        name: name.to_string(),
+        offset: 0,
+        reason: "sprite lump names must be a name, a frame letter and a rotation digit".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::Lump;
+
+    /// Builds a WAD file with the given lumps in the sprite namespace
+    fn sprite_wad(names: &[&str]) -> WadFile {
+        let sprites = names.iter().map(|name| Lump::new(name, vec![0; 8]));
+        WadFile::from_lumps(
+            std::iter::once(Lump::new(SPRITES_START, Vec::new()))
+                .chain(sprites)
+                .chain(std::iter::once(Lump::new(SPRITES_END, Vec::new())))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn mirrored_lumps_fill_both_rotations() {
+        let catalog = SpriteCatalog::load(&sprite_wad(&["TROOA1", "TROOA2A8"]));
+        assert_eq!(catalog.lookup("TROO", 'A', 1), Some((1, false)));
+        assert_eq!(catalog.lookup("TROO", 'A', 2), Some((2, false)));
+        assert_eq!(catalog.lookup("TROO", 'A', 8), Some((2, true)));
+        assert_eq!(catalog.lookup("TROO", 'A', 3), None);
+    }
+
+    #[test]
+    fn frames_without_rotations_are_used_for_every_angle() {
+        let catalog = SpriteCatalog::load(&sprite_wad(&["BAR1A0", "BAR1B0"]));
+        let frame = catalog.get("BAR1").unwrap().frames[1].as_ref().unwrap();
+        assert!(!frame.rotate && frame.is_complete());
+        assert_eq!(catalog.lookup("BAR1", 'B', 5), Some((2, false)));
+    }
+
+    #[test]
+    fn later_lumps_replace_rotation_0_and_rotations() {
+        // A PWAD replacing the rotations of a frame with a single lump, and the other way around
+        let catalog = SpriteCatalog::load(&sprite_wad(&["TROOA1", "TROOA2A8", "TROOA0"]));
+        let frame = catalog.get("TROO").unwrap().frames[0].as_ref().unwrap();
+        assert!(!frame.rotate);
+        assert_eq!(frame.lumps, [Some(3); NUM_ROTATIONS]);
+        assert_eq!(catalog.lookup("TROO", 'A', 8), Some((3, false)));
+
+        let catalog = SpriteCatalog::load(&sprite_wad(&["POSSA0", "POSSA1"]));
+        let frame = catalog.get("POSS").unwrap().frames[0].as_ref().unwrap();
+        assert!(frame.rotate && !frame.is_complete());
+        assert_eq!(catalog.lookup("POSS", 'A', 1), Some((2, false)));
+        assert_eq!(catalog.lookup("POSS", 'A', 2), None);
+    }
+
+    #[test]
+    fn malformed_names_are_skipped() {
+        let catalog = SpriteCatalog::load(&sprite_wad(&["TROOA1", "TROOA9", "BAD", "POSSA1A"]));
+        assert_eq!(catalog.names().count(), 1);
+        assert_eq!(catalog.lookup("TROO", 'A', 1), Some((1, false)));
+        assert_eq!(catalog.errors().len(), 3);
+        assert!(catalog
+            .errors()
+            .iter()
+            .all(|error| matches!(error, WadError::BadHeader { .. })));
+    }
+}
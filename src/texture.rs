@@ -0,0 +1,508 @@
+use crate::wad::{Column, LumpReader, Patch, WadError, WadFile};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const PATCH_NAMES_LUMP_NAME: &str = "PNAMES";
+const TEXTURE_LUMP_NAMES: [&str; 2] = ["TEXTURE1", "TEXTURE2"];
+/// The texture name used by sidedefs that have no texture on that part
+pub const NO_TEXTURE: &str = "-";
+/// The height at which vanilla's wall renderer repeats a texture
+pub const WALL_TILE_HEIGHT: usize = 128;
+
+/// A patch placed in a texture, as described by TEXTURE1/TEXTURE2
+#[derive(Debug, Clone)]
+pub struct TexturePatch {
+    /// The position of the patch in the texture
+    pub origin_x: i16,
+    pub origin_y: i16,
+    /// The index into PNAMES
+    pub patch: usize,
+}
+
+/// A texture description from TEXTURE1/TEXTURE2
+#[derive(Debug, Clone)]
+pub struct TextureDef {
+    pub name: String,
+    pub masked: bool,
+    pub width: usize,
+    pub height: usize,
+    pub patches: Vec<TexturePatch>,
+}
+
+/// A wall texture, composited from its patches
+/// # Remarks
+/// The pixels are stored column by column, which is how the wall renderer draws them.
+pub struct Texture {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    /// The palette indices, column by column
+    pixels: Vec<u8>,
+    /// Whether a patch covers the pixel, column by column
+    opaque: Vec<bool>,
+    /// The PNAMES entries this texture uses that don't exist in the WAD file
+    pub missing_patches: Vec<String>,
+}
+
+impl Texture {
+    /// Gets the palette indices of a column
+    /// # Remarks
+    /// The column wraps with vanilla's texture width mask, the largest power of two that fits in
+    /// the width. The columns past it are never drawn, so a 96 pixel wide texture repeats its
+    /// first 64 columns.
+    pub fn column(&self, x: usize) -> &[u8] {
+        let x = x & self.width_mask();
+        &self.pixels[x * self.height..(x + 1) * self.height]
+    }
+
+    /// Gets which pixels of a column are covered by a patch, used to draw masked textures
+    pub fn column_mask(&self, x: usize) -> &[bool] {
+        let x = x & self.width_mask();
+        &self.opaque[x * self.height..(x + 1) * self.height]
+    }
+
+    /// Gets the palette index the wall renderer draws at a position
+    /// # Remarks
+    /// Like vanilla's R_DrawColumn, rows wrap every 128 pixels, so taller textures repeat their
+    /// first 128 rows. Vanilla reads rows past the bottom of a shorter texture from whatever
+    /// follows it in memory, which shows as garbage; here they wrap around the texture instead.
+    pub fn wall_pixel(&self, x: usize, y: usize) -> u8 {
+        let column = self.column(x);
+        column[(y % WALL_TILE_HEIGHT) % column.len()]
+    }
+
+    /// Gets the mask used to wrap columns, like vanilla's texturewidthmask
+    fn width_mask(&self) -> usize {
+        (1 << self.width.ilog2()) - 1
+    }
+}
+
+/// All wall textures of a WAD file
+/// # Remarks
+/// The texture descriptions are parsed up front, while the textures themselves are composited on
+/// first use and cached afterwards. The patches come from the WAD file's own cache. Usually this
+/// is used through `WadFile::get_texture` rather than directly.
+pub struct TextureSet {
+    /// The names from PNAMES
+    pub patch_names: Vec<String>,
+    /// The texture descriptions, in texture number order
+    pub definitions: Vec<TextureDef>,
+    /// The texture numbers by name
+    numbers: HashMap<String, usize>,
+    /// The composited textures, by texture number
+    textures: Vec<Option<Rc<Texture>>>,
+}
+
+impl TextureSet {
+    /// Parses PNAMES, TEXTURE1 and, when present, TEXTURE2
+    /// # Examples
+    /// ```no_run
+    /// use doom::texture::TextureSet;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mut textures = TextureSet::load(&wad).unwrap();
+    /// let texture = textures.get(&wad, "STARTAN3").unwrap();
+    /// let column = texture.column(17);
+    /// ```
+    pub fn load(wad: &WadFile) -> Result<Self, WadError> {
+        let patch_names = decode_patch_names(wad.require_lump(PATCH_NAMES_LUMP_NAME)?)?;
+
+        let mut definitions = Vec::new();
+        for lump_name in TEXTURE_LUMP_NAMES {
+            // TEXTURE2 only exists in the registered and commercial games
+            if wad.get_lump_index(lump_name).is_some() {
+                let data = wad.require_lump(lump_name)?;
+                definitions.extend(decode_texture_defs(lump_name, data, patch_names.len())?);
+            }
+        }
+        if definitions.is_empty() {
+            return Err(WadError::MissingLump {
+                name: TEXTURE_LUMP_NAMES[0].to_string(),
+            });
+        }
+
+        // The first definition with a name wins, like vanilla's R_TextureNumForName
+        let numbers = definitions
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(number, definition)| (definition.name.clone(), number))
+            .collect();
+        let textures = vec![None; definitions.len()];
+
+        Ok(Self {
+            patch_names,
+            definitions,
+            numbers,
+            textures,
+        })
+    }
+
+    /// Gets the texture number for a name
+    /// # Returns
+    /// The texture number, or `None` for `-` and unknown names
+    pub fn number(&self, name: &str) -> Option<usize> {
+        match name {
+            NO_TEXTURE => None,
+            _ => self.numbers.get(&name.to_ascii_uppercase()).copied(),
+        }
+    }
+
+    /// Gets a texture by name, compositing it on first use
+    pub fn get(&mut self, wad: &WadFile, name: &str) -> Result<Rc<Texture>, WadError> {
+        let number = self.number(name).ok_or_else(|| WadError::MissingLump {
+            name: name.to_string(),
+        })?;
+        self.get_by_number(wad, number)
+    }
+
+    /// Gets a texture by number, compositing it on first use
+    pub fn get_by_number(&mut self, wad: &WadFile, number: usize) -> Result<Rc<Texture>, WadError> {
+        if let Some(texture) = &self.textures[number] {
+            return Ok(texture.clone());
+        }
+
+        let texture = Rc::new(self.composite(wad, number)?);
+        self.textures[number] = Some(texture.clone());
+        Ok(texture)
+    }
+
+    /// Drops all composited textures
+    pub fn clear_cache(&mut self) {
+        self.textures.iter_mut().for_each(|texture| *texture = None);
+    }
+
+    /// Draws the patches of a texture on top of each other
+    /// # Remarks
+    /// This follows vanilla's R_GenerateLookup and R_DrawColumnInCache, including their quirks:
+    /// * The patch's own offsets are ignored and later patches overwrite earlier ones.
+    /// * A column covered by a single patch is drawn straight from that patch, which ignores the
+    ///   patch's vertical origin in the texture.
+    /// * In columns covered by several patches, a post that starts above the texture is clipped
+    ///   without skipping its first pixels, so the rest of it is shifted up.
+    ///
+    /// Unlike vanilla, which quits with "Missing patch in texture", patches that don't exist in
+    /// the WAD file are skipped and listed in `missing_patches`.
+    fn composite(&mut self, wad: &WadFile, number: usize) -> Result<Texture, WadError> {
+        let definition = self.definitions[number].clone();
+        let (width, height) = (definition.width, definition.height);
+        let mut pixels = vec![0; width * height];
+        let mut opaque = vec![false; width * height];
+        let mut missing_patches = Vec::new();
+
+        let mut patches = Vec::with_capacity(definition.patches.len());
+        for texture_patch in &definition.patches {
+            match self.patch(wad, texture_patch.patch)? {
+                Some(patch) => patches.push((texture_patch, patch)),
+                None => missing_patches.push(self.patch_names[texture_patch.patch].clone()),
+            }
+        }
+
+        // The amount of patches covering each column
+        let mut patch_counts = vec![0; width];
+        for (texture_patch, patch) in &patches {
+            for x in texture_columns(texture_patch, patch, width) {
+                patch_counts[x.0] += 1;
+            }
+        }
+
+        for (texture_patch, patch) in &patches {
+            for (x, column) in texture_columns(texture_patch, patch, width) {
+                let single_patch = patch_counts[x] == 1;
+                for post in &column.posts {
+                    let (top, data) = match single_patch {
+                        true => (post.top as isize, &post.data[..]),
+                        false => {
+                            let top = texture_patch.origin_y as isize + post.top as isize;
+                            // Vanilla shortens the post but still copies from its first pixel
+                            let clipped = (-top).clamp(0, post.data.len() as isize) as usize;
+                            (top.max(0), &post.data[..post.data.len() - clipped])
+                        }
+                    };
+                    for (i, &color) in data.iter().enumerate() {
+                        let y = top + i as isize;
+                        if y < 0 || y >= height as isize {
+                            continue;
+                        }
+                        let pos = x * height + y as usize;
+                        pixels[pos] = color;
+                        opaque[pos] = true;
+                    }
+                }
+            }
+        }
+
+        Ok(Texture {
+            name: definition.name,
+            width,
+            height,
+            pixels,
+            opaque,
+            missing_patches,
+        })
+    }
+
+    /// Gets a decoded patch by PNAMES index
+    /// # Returns
+    /// The patch, or `None` if there is no lump with that name
+    fn patch(&self, wad: &WadFile, index: usize) -> Result<Option<Rc<Patch>>, WadError> {
+        let name = &self.patch_names[index];
+        match wad.get_lump_index(name) {
+            Some(_) => wad.get_patch(name).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Gets the columns of a patch that lie within a texture, along with their texture column
+fn texture_columns<'a>(
+    texture_patch: &TexturePatch,
+    patch: &'a Patch,
+    width: usize,
+) -> impl Iterator<Item = (usize, &'a Column)> {
+    let origin_x = texture_patch.origin_x as isize;
+    patch
+        .columns
+        .iter()
+        .enumerate()
+        .map(move |(patch_x, column)| (origin_x + patch_x as isize, column))
+        .filter(move |&(x, _)| x >= 0 && x < width as isize)
+        .map(|(x, column)| (x as usize, column))
+}
+
+/// Decodes the PNAMES lump, the list of patch names that texture definitions refer to
+fn decode_patch_names(data: &[u8]) -> Result<Vec<String>, WadError> {
+    let mut reader = LumpReader::new(PATCH_NAMES_LUMP_NAME, data);
+    let count = reader.read_i32()?.max(0);
+    (0..count).map(|_| reader.read_name()).collect()
+}
+
+/// Decodes a TEXTURE1 or TEXTURE2 lump
+fn decode_texture_defs(
+    name: &str,
+    data: &[u8],
+    patch_count: usize,
+) -> Result<Vec<TextureDef>, WadError> {
+    let mut reader = LumpReader::new(name, data);
+    let count = reader.read_i32()?.max(0);
+    let offsets = (0..count)
+        .map(|_| reader.read_i32())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    offsets
+        .iter()
+        .map(|&offset| {
+            if offset < 0 || offset as usize >= data.len() {
+                return Err(WadError::BadHeader {
+                    name: name.to_string(),
+                    offset: reader.position(),
+                    reason: format!("texture definition offset {} is out of bounds", offset),
+                });
+            }
+            reader.set_position(offset as usize);
+            decode_texture_def(&mut reader, patch_count)
+        })
+        .collect()
+}
+
+fn decode_texture_def(reader: &mut LumpReader, patch_count: usize) -> Result<TextureDef, WadError> {
+    let name = reader.read_name()?;
+    let masked = reader.read_i32()? != 0;
+    let width = reader.read_i16()?;
+    let height = reader.read_i16()?;
+    reader.read_i32()?; // Obsolete column directory
+    let patches = reader.read_i16()?.max(0);
+
+    if width <= 0 || height <= 0 {
+        return Err(WadError::BadHeader {
+            name: reader.name().to_string(),
+            offset: reader.position(),
+            reason: format!("texture {} has an invalid size {}x{}", name, width, height),
+        });
+    }
+
+    let patches = (0..patches)
+        .map(|_| {
+            let origin_x = reader.read_i16()?;
+            let origin_y = reader.read_i16()?;
+            let patch = reader.read_i16()?;
+            reader.read_i16()?; // Unused step direction
+            reader.read_i16()?; // Unused colormap
+            if patch < 0 || patch as usize >= patch_count {
+                return Err(WadError::BadReference {
+                    name: reader.name().to_string(),
+                    offset: reader.position(),
+                    reason: format!(
+                        "texture {} uses patch {} but PNAMES has only {}",
+                        name, patch, patch_count
+                    ),
+                });
+            }
+            Ok(TexturePatch {
+                origin_x,
+                origin_y,
+                patch: patch as usize,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(TextureDef {
+        name,
+        masked,
+        width: width as usize,
+        height: height as usize,
+        patches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::{encode_picture, Lump, Picture};
+
+    /// Encodes a patch whose pixels are given column by column
+    fn patch(name: &str, columns: &[&[u8]]) -> Lump {
+        let (width, height) = (columns.len(), columns[0].len());
+        let pixels = (0..width * height)
+            .map(|i| columns[i % width][i / width])
+            .collect();
+        let picture = Picture {
+            width,
+            height,
+            left_offset: 5,
+            top_offset: 5,
+            pixels,
+            opaque: vec![true; width * height],
+        };
+        Lump::new(name, encode_picture(name, &picture).unwrap())
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut data = name.as_bytes().to_vec();
+        data.resize(8, 0);
+        data
+    }
+
+    /// Encodes PNAMES and a TEXTURE1 lump with a single texture
+    /// # Arguments
+    /// * `size` - The width and height of the texture
+    /// * `patches` - The origin and PNAMES index of each patch
+    fn texture_lumps(
+        patch_names: &[&str],
+        size: (i16, i16),
+        patches: &[(i16, i16, i16)],
+    ) -> Vec<Lump> {
+        let mut pnames = (patch_names.len() as i32).to_le_bytes().to_vec();
+        patch_names
+            .iter()
+            .for_each(|patch| pnames.extend(name(patch)));
+
+        let mut textures = [1i32.to_le_bytes(), 8i32.to_le_bytes()].concat();
+        textures.extend(name("WALL"));
+        textures.extend(0i32.to_le_bytes());
+        textures.extend(size.0.to_le_bytes());
+        textures.extend(size.1.to_le_bytes());
+        textures.extend(0i32.to_le_bytes());
+        textures.extend((patches.len() as i16).to_le_bytes());
+        for &(x, y, patch) in patches {
+            [x, y, patch, 1, 0]
+                .iter()
+                .for_each(|value| textures.extend(value.to_le_bytes()));
+        }
+
+        vec![
+            Lump::new(PATCH_NAMES_LUMP_NAME, pnames),
+            Lump::new("TEXTURE1", textures),
+        ]
+    }
+
+    fn wall(lumps: Vec<Lump>) -> Rc<Texture> {
+        let wad = WadFile::from_lumps(lumps);
+        TextureSet::load(&wad).unwrap().get(&wad, "wall").unwrap()
+    }
+
+    #[test]
+    fn later_patches_overwrite_earlier_ones() {
+        let mut lumps = texture_lumps(&["BRICK", "SWITCH"], (4, 4), &[(0, 0, 0), (2, 1, 1)]);
+        lumps.push(patch("BRICK", &[&[1; 4][..]; 4]));
+        lumps.push(patch("SWITCH", &[&[2, 3], &[4, 5], &[6, 7]]));
+        let texture = wall(lumps);
+
+        assert_eq!((texture.width, texture.height), (4, 4));
+        assert_eq!(texture.column(0), &[1, 1, 1, 1]);
+        assert_eq!(texture.column(2), &[1, 2, 3, 1]);
+        // The last column of the patch is past the edge of the texture
+        assert_eq!(texture.column(3), &[1, 4, 5, 1]);
+        assert!(texture.column_mask(3).iter().all(|&opaque| opaque));
+        assert!(texture.missing_patches.is_empty());
+    }
+
+    #[test]
+    fn single_patch_columns_ignore_the_vertical_origin() {
+        let mut lumps = texture_lumps(&["BRICK", "SWITCH"], (2, 4), &[(0, 2, 0), (1, 0, 1)]);
+        lumps.push(patch("BRICK", &[&[1, 2], &[3, 4]]));
+        lumps.push(patch("SWITCH", &[&[5, 6]]));
+        let texture = wall(lumps);
+
+        assert_eq!(texture.column(0), &[1, 2, 0, 0]);
+        assert_eq!(texture.column_mask(0), &[true, true, false, false]);
+        // Column 1 has both patches, so the first one keeps its origin
+        assert_eq!(texture.column(1), &[5, 6, 3, 4]);
+    }
+
+    #[test]
+    fn posts_clipped_at_the_top_keep_their_first_pixels() {
+        let mut lumps = texture_lumps(&["BRICK", "SWITCH"], (1, 4), &[(0, 0, 0), (0, -1, 1)]);
+        lumps.push(patch("BRICK", &[&[1; 4]]));
+        lumps.push(patch("SWITCH", &[&[5, 6, 7]]));
+        let texture = wall(lumps);
+
+        assert_eq!(texture.column(0), &[5, 6, 1, 1]);
+    }
+
+    #[test]
+    fn missing_patches_are_skipped() {
+        let mut lumps = texture_lumps(&["BRICK", "NOPATCH"], (1, 2), &[(0, 0, 0), (0, 0, 1)]);
+        lumps.push(patch("BRICK", &[&[1, 2]]));
+        let texture = wall(lumps);
+
+        assert_eq!(texture.column(0), &[1, 2]);
+        assert_eq!(texture.missing_patches, vec!["NOPATCH".to_string()]);
+    }
+
+    #[test]
+    fn columns_wrap_with_the_width_mask() {
+        let mut lumps = texture_lumps(&["BRICK"], (3, 1), &[(0, 0, 0)]);
+        lumps.push(patch("BRICK", &[&[1], &[2], &[3]]));
+        let texture = wall(lumps);
+
+        // The mask is 1, so the last column is never drawn
+        assert_eq!(texture.column(1), &[2]);
+        assert_eq!(texture.column(2), &[1]);
+        assert_eq!(texture.column(3), &[2]);
+    }
+
+    #[test]
+    fn wall_rows_repeat_every_128_pixels() {
+        let rows = (0..136).map(|y| y as u8).collect::<Vec<_>>();
+        let mut lumps = texture_lumps(&["TALL"], (1, 136), &[(0, 0, 0)]);
+        lumps.push(patch("TALL", &[&rows]));
+        let texture = wall(lumps);
+
+        assert_eq!(texture.column(0)[130], 130);
+        assert_eq!(texture.wall_pixel(0, 130), 2);
+
+        let mut lumps = texture_lumps(&["BRICK"], (1, 3), &[(0, 0, 0)]);
+        lumps.push(patch("BRICK", &[&[1, 2, 3]]));
+        assert_eq!(wall(lumps).wall_pixel(0, 4), 2);
+    }
+
+    #[test]
+    fn bad_patch_numbers_are_rejected() {
+        let lumps = texture_lumps(&["BRICK"], (1, 1), &[(0, 0, 1)]);
+        assert!(matches!(
+            TextureSet::load(&WadFile::from_lumps(lumps)),
+            Err(WadError::BadReference { .. })
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::flat::Flat;
+use crate::image_tools::convert_to_color;
+use crate::wad::{Palette, Picture};
+use crate::{HEIGHT, WIDTH};
+use std::rc::Rc;
+
+/// The amount of palettes in PLAYPAL
+pub const NUM_PALETTES: usize = 14;
+/// The first of the palettes that tint the screen red when taking damage or using the berserk pack
+pub const DAMAGE_PALETTE_START: usize = 1;
+pub const NUM_DAMAGE_PALETTES: usize = 8;
+/// The first of the palettes that tint the screen gold when picking up items
+pub const BONUS_PALETTE_START: usize = 9;
+pub const NUM_BONUS_PALETTES: usize = 4;
+/// The palette that tints the screen green while wearing the radiation suit
+pub const RADIATION_PALETTE: usize = 13;
+
+/// The 320x200 screen
+/// # Remarks
+/// Everything is drawn as palette indices, like the original. The indices are only converted to
+/// colors when the screen is presented, using the active palette, so switching palettes (e.g. the
+/// red damage flash) tints the whole screen at once.
+pub struct Screen {
+    /// The palette indices, row by row
+    pub pixels: Vec<u8>,
+    palettes: Rc<[Palette]>,
+    palette: usize,
+}
+
+impl Screen {
+    /// Creates a new screen
+    /// # Arguments
+    /// * `palettes` - The palettes from PLAYPAL, the first one is active
+    /// # Examples
+    /// ```no_run
+    /// use doom::video::Screen;
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let mut screen = Screen::new(wad.get_palettes().unwrap());
+    /// screen.set_palette(doom::video::RADIATION_PALETTE);
+    /// ```
+    pub fn new(palettes: Rc<[Palette]>) -> Self {
+        Self {
+            pixels: vec![0; WIDTH * HEIGHT],
+            palettes,
+            palette: 0,
+        }
+    }
+
+    /// Gets the index of the active palette
+    pub fn palette(&self) -> usize {
+        self.palette
+    }
+
+    /// Selects the palette used when presenting the screen
+    /// # Remarks
+    /// Indices past the palettes in PLAYPAL are ignored.
+    pub fn set_palette(&mut self, palette: usize) {
+        if palette < self.palettes.len() {
+            self.palette = palette;
+        }
+    }
+
+    /// Fills the screen with a single palette index
+    pub fn clear(&mut self, color: u8) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = color);
+    }
+
+    /// Draws a picture, honouring its offsets
+    /// # Arguments
+    /// * `picture` - The picture to draw
+    /// * `x_pos` - The x position to draw at, the picture's left offset is subtracted from it
+    /// * `y_pos` - The y position to draw at, the picture's top offset is subtracted from it
+    /// # Remarks
+    /// Only the pixels covered by the picture's posts are drawn, the gaps between them are left
+    /// alone. The picture is clipped against the edges of the screen.
+    pub fn draw_picture(&mut self, picture: &Picture, x_pos: isize, y_pos: isize) {
+        let left = x_pos - picture.left_offset as isize;
+        let top = y_pos - picture.top_offset as isize;
+        for y in 0..picture.height {
+            let screen_y = top + y as isize;
+            if !(0..HEIGHT as isize).contains(&screen_y) {
+                continue;
+            }
+            for x in 0..picture.width {
+                let screen_x = left + x as isize;
+                if picture.is_opaque(x, y) && (0..WIDTH as isize).contains(&screen_x) {
+                    self.pixels[screen_y as usize * WIDTH + screen_x as usize] =
+                        picture.pixel(x, y);
+                }
+            }
+        }
+    }
+
+    /// Fills the screen by repeating a flat, like the backgrounds of the text screens
+    pub fn draw_tiled(&mut self, flat: &Flat) {
+        self.pixels
+            .iter_mut()
+            .enumerate()
+            .for_each(|(pos, pixel)| *pixel = flat.pixel(pos % WIDTH, pos / WIDTH));
+    }
+
+    /// Converts the screen to colors with the active palette
+    /// # Arguments
+    /// * `buffer` - The window buffer, WIDTH * HEIGHT ARGB colors
+    pub fn present(&self, buffer: &mut [u32]) {
+        let palette = &self.palettes[self.palette];
+        buffer
+            .iter_mut()
+            .zip(&self.pixels)
+            .for_each(|(color, &index)| *color = convert_to_color(palette[index as usize]));
+    }
+}
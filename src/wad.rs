@@ -1,57 +1,1034 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use image::{DynamicImage, ImageBuffer, Rgb};
-use rodio::Source;
+use crate::audio::{SfxDevice, Sound};
+use crate::cache::ResourceCache;
+use crate::flat::Flat;
+use crate::genmidi::GenMidi;
+use crate::image_tools::{indexed_to_image, nearest_color};
+use crate::music::Mus;
+use crate::pc_speaker::PcSound;
+use crate::texture::{Texture, TextureSet};
+use crate::wad_search::find_wad_file;
+use byteorder::{ByteOrder, LittleEndian};
+use image::{DynamicImage, GenericImageView};
+use std::cell::OnceCell;
 use std::collections::HashMap;
-use std::env;
-use std::io::{Cursor, Read};
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 const PALETTE_LUMP_NAME: &str = "PLAYPAL";
+const COLORMAP_LUMP_NAME: &str = "COLORMAP";
+const GENMIDI_LUMP_NAME: &str = "GENMIDI";
+pub const SPRITES_START: &str = "S_START";
+pub const SPRITES_END: &str = "S_END";
+pub const FLATS_START: &str = "F_START";
+pub const FLATS_END: &str = "F_END";
+/// The size of a single palette in PLAYPAL, 256 RGB colors
+const PALETTE_SIZE: usize = 256 * 3;
+/// The amount of tables in COLORMAP: 32 light levels, the invulnerability map and an unused black one
+pub const NUM_COLORMAP_TABLES: usize = 34;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG";
+/// The size of the WAD header: the identification, the lump count and the directory offset
+const WAD_HEADER_SIZE: usize = 12;
+/// The size of a directory entry: the offset, the size and the name of a lump
+const DIRECTORY_ENTRY_SIZE: usize = 16;
+/// The name used to report errors in the WAD header, which belongs to no lump
+const DIRECTORY_NAME: &str = "directory";
 
+/// The lumps that make up a map block, in the order they follow the map marker (e.g. `E1M1`)
+pub const MAP_LUMP_NAMES: [&str; 10] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP",
+];
+
+/// A single entry of the WAD directory
+/// # Remarks
+/// Lumps loaded from a file are read from it the first time their data is asked for, so the
+/// lumps that are never used are never read.
+pub struct Lump {
+    /// The name of the lump, upper cased
+    pub name: String,
+    size: usize,
+    source: LumpSource,
+    data: OnceCell<Box<[u8]>>,
+}
+
+/// Where the data of a lump comes from
+enum LumpSource {
+    /// The data was given when the lump was created
+    Memory,
+    /// The data is read from a WAD file on first use
+    File { file: Rc<OpenWad>, offset: u64 },
+}
+
+/// A WAD file that is kept open to read lumps from
+struct OpenWad {
+    path: PathBuf,
+    file: File,
+}
+
+impl Lump {
+    /// Creates a lump from data in memory
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_ascii_uppercase(),
+            size: data.len(),
+            source: LumpSource::Memory,
+            data: OnceCell::from(data.into_boxed_slice()),
+        }
+    }
+
+    /// Creates an empty marker lump, like `S_START` or `E1M1`
+    pub fn marker(name: &str) -> Self {
+        Self::new(name, Vec::new())
+    }
+
+    /// The size of the lump data in bytes, known without reading the data
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The offset of the lump data in its WAD file, or `None` for lumps created in memory
+    pub fn offset(&self) -> Option<u64> {
+        match &self.source {
+            LumpSource::File { offset, .. } => Some(*offset),
+            LumpSource::Memory => None,
+        }
+    }
+
+    /// Gets the data of the lump, reading it from the WAD file on first use
+    pub fn data(&self) -> Result<&[u8], WadError> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = self.read_file(self.size)?;
+        Ok(self.data.get_or_init(|| data.into_boxed_slice()))
+    }
+
+    /// Gets the first bytes of the lump, e.g. to tell what it holds from its header
+    /// # Returns
+    /// Up to `count` bytes; fewer if the lump is shorter
+    /// # Remarks
+    /// Unlike `data`, this only reads the requested bytes from the WAD file and doesn't keep them.
+    pub fn read_start(&self, count: usize) -> Result<Vec<u8>, WadError> {
+        let count = count.min(self.size);
+        match self.data.get() {
+            Some(data) => Ok(data[..count].to_vec()),
+            None => self.read_file(count),
+        }
+    }
+
+    /// Reads the first bytes of a lump that is still in its WAD file
+    fn read_file(&self, count: usize) -> Result<Vec<u8>, WadError> {
+        let (wad, offset) = match &self.source {
+            LumpSource::File { file, offset } => (file, *offset),
+            LumpSource::Memory => unreachable!("Lumps in memory always have their data"),
+        };
+        let mut data = vec![0; count];
+        // Reading through a shared reference to the file keeps lumps borrowable
+        let mut file = &wad.file;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut data))
+            .map_err(|source| WadError::Io {
+                path: wad.path.clone(),
+                source,
+            })?;
+        Ok(data)
+    }
+}
+
+/// A loaded WAD file
+/// # Remarks
+/// The lumps are kept in directory order, including duplicates. Doom relies on this order: the
+/// map lumps (THINGS, LINEDEFS, ...) repeat after every map marker, and sprites and flats are
+/// grouped between S_START/S_END and F_START/F_END markers.
+///
+/// Decoded palettes, pictures, flats, textures, sounds and music are cached, see `clear_cache`.
 pub struct WadFile {
-    // create a tuple array with an id, name and byte array
-    pub lumps: HashMap<String, Vec<u8>>,
+    /// All lumps in directory order
+    pub lumps: Vec<Lump>,
+    /// The directory indices of every lump, grouped by name
+    names: HashMap<String, Vec<usize>>,
+    cache: ResourceCache,
+}
+
+/// 256 RGB colors, indexed by the pixels of pictures, flats and textures
+pub type Palette = Vec<[u8; 3]>;
+
+/// A table that maps palette indices to other palette indices, e.g. to darken them
+pub type Colormap = [u8; 256];
+
+/// The errors that can occur while loading a WAD file or decoding its lumps
+#[derive(Debug)]
+pub enum WadError {
+    /// The WAD file could not be read
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// No IWAD was found in any of the searched paths
+    IwadNotFound { searched: Vec<PathBuf> },
+    /// A required lump is not part of the WAD file
+    MissingLump { name: String },
+    /// The lump ends before all of its data could be read
+    TruncatedLump { name: String, offset: usize },
+    /// A picture column starts outside of the lump
+    BadColumnOffset {
+        name: String,
+        column: usize,
+        offset: usize,
+    },
+    /// A header holds values that make no sense, like a negative picture size
+    BadHeader {
+        name: String,
+        offset: usize,
+        reason: String,
+    },
+    /// The lump is stored in a format that can't be decoded
+    UnsupportedFormat {
+        name: String,
+        offset: usize,
+        reason: String,
+    },
+    /// A record references an entry that does not exist, like a linedef using a missing vertex
+    BadReference {
+        name: String,
+        offset: usize,
+        reason: String,
+    },
+    /// A lump name that can't be stored in a WAD directory
+    BadLumpName { name: String },
+    /// Data that can't be encoded in a lump, like a picture that is too large
+    Unencodable { name: String, reason: String },
 }
 
-fn get_wad_dir() -> PathBuf {
-    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        // If the CARGO_MANIFEST_DIR environment variable is set, we're probably running with `cargo run`
-        // CARGO_MANIFEST_DIR points to the directory where your Cargo.toml exists.
-        PathBuf::from(manifest_dir).join("wad")
-    } else {
-        // Otherwise, we're probably running the program directly
-        // env::current_exe() gives us the path of the current executable
-        PathBuf::from(env::current_exe().unwrap().parent().unwrap()).join("wad")
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WadError::Io { path, source } => {
+                write!(f, "unable to read {}: {}", path.display(), source)
+            }
+            WadError::IwadNotFound { searched } => {
+                write!(f, "no IWAD found, searched:")?;
+                searched
+                    .iter()
+                    .try_for_each(|path| write!(f, "\n  {}", path.display()))
+            }
+            WadError::MissingLump { name } => write!(f, "lump {} not found", name),
+            WadError::TruncatedLump { name, offset } => {
+                write!(f, "lump {} is truncated at offset {}", name, offset)
+            }
+            WadError::BadColumnOffset {
+                name,
+                column,
+                offset,
+            } => write!(
+                f,
+                "lump {} has a bad offset {} for column {}",
+                name, offset, column
+            ),
+            WadError::BadHeader {
+                name,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "lump {} has a bad header at offset {}: {}",
+                name, offset, reason
+            ),
+            WadError::UnsupportedFormat {
+                name,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "lump {} has an unsupported format at offset {}: {}",
+                name, offset, reason
+            ),
+            WadError::BadReference {
+                name,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "lump {} has a bad reference at offset {}: {}",
+                name, offset, reason
+            ),
+            WadError::BadLumpName { name } => write!(
+                f,
+                "{:?} is not a valid lump name, names are 1 to 8 ASCII characters",
+                name
+            ),
+            WadError::Unencodable { name, reason } => {
+                write!(f, "unable to encode lump {}: {}", name, reason)
+            }
+        }
     }
 }
 
-impl WadFile {
-    pub fn load(path: &str) -> Self {
-        let wad = wad::load_wad_file(get_wad_dir().join(path)).expect("Failed to load WAD file");
-        let lumps = wad.entry_iter().fold(HashMap::new(), |mut result, item| {
-            result.insert(item.display_name().to_string(), item.lump.to_vec());
+impl std::error::Error for WadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WadError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Reads little endian values from a lump
+/// # Remarks
+/// Reading past the end of the lump returns a `TruncatedLump` error holding the lump name and the
+/// offset of the failed read.
+pub struct LumpReader<'a> {
+    name: &'a str,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> LumpReader<'a> {
+    pub fn new(name: &'a str, data: &'a [u8]) -> Self {
+        Self {
+            name,
+            data,
+            position: 0,
+        }
+    }
+
+    /// The name of the lump being read
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The offset of the next read
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    /// The amount of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    /// Reads `length` bytes
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], WadError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(length))
+            .ok_or_else(|| WadError::TruncatedLump {
+                name: self.name.to_string(),
+                offset: self.position,
+            })?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, WadError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, WadError> {
+        Ok(LittleEndian::read_i16(self.read_bytes(2)?))
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, WadError> {
+        Ok(LittleEndian::read_u16(self.read_bytes(2)?))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, WadError> {
+        Ok(LittleEndian::read_i32(self.read_bytes(4)?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, WadError> {
+        Ok(LittleEndian::read_u32(self.read_bytes(4)?))
+    }
+
+    /// Reads a lump or texture name, which is padded with zeroes to 8 bytes
+    pub fn read_name(&mut self) -> Result<String, WadError> {
+        Ok(decode_name(self.read_bytes(8)?))
+    }
+}
+
+/// Resolves a WAD file path
+/// # Remarks
+/// Paths that exist as given (relative to the working directory or absolute) are used as is,
+/// bare file names are looked up in the WAD search directories.
+fn resolve_wad_path(path: &Path) -> PathBuf {
+    find_wad_file(path).unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Reads the directory of a single WAD file
+/// # Remarks
+/// Only the header and the directory are read; the lumps keep the file open and read their data
+/// on first use. Lumps that reach past the end of the file are rejected up front.
+fn read_lumps(path: &Path) -> Result<Vec<Lump>, WadError> {
+    let io_error = |source| WadError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let bad_header = |offset, reason: String| WadError::BadHeader {
+        name: path.display().to_string(),
+        offset,
+        reason,
+    };
+
+    let mut file = File::open(path).map_err(io_error)?;
+    let file_size = file.metadata().map_err(io_error)?.len();
+    let mut header = [0; WAD_HEADER_SIZE];
+    file.read_exact(&mut header).map_err(io_error)?;
+    if &header[..4] != b"IWAD" && &header[..4] != b"PWAD" {
+        return Err(bad_header(0, "not an IWAD or PWAD file".to_string()));
+    }
+    let count = LittleEndian::read_i32(&header[4..8]);
+    let directory_offset = LittleEndian::read_i32(&header[8..12]);
+    let directory_size = count as i64 * DIRECTORY_ENTRY_SIZE as i64;
+    if count < 0
+        || directory_offset < 0
+        || directory_offset as i64 + directory_size > file_size as i64
+    {
+        return Err(bad_header(
+            4,
+            format!(
+                "the directory of {} lumps at offset {} doesn't fit in the file",
+                count, directory_offset
+            ),
+        ));
+    }
+
+    let mut directory = vec![0; directory_size as usize];
+    file.seek(SeekFrom::Start(directory_offset as u64))
+        .and_then(|_| file.read_exact(&mut directory))
+        .map_err(io_error)?;
+
+    let wad = Rc::new(OpenWad {
+        path: path.to_path_buf(),
+        file,
+    });
+    directory
+        .chunks_exact(DIRECTORY_ENTRY_SIZE)
+        .enumerate()
+        .map(|(i, entry)| {
+            let offset = LittleEndian::read_i32(&entry[0..4]);
+            let size = LittleEndian::read_i32(&entry[4..8]);
+            let name = decode_name(&entry[8..16]);
+            if offset < 0 || size < 0 || offset as u64 + size as u64 > file_size {
+                return Err(bad_header(
+                    directory_offset as usize + i * DIRECTORY_ENTRY_SIZE,
+                    format!("lump {} reaches past the end of the file", name),
+                ));
+            }
+            Ok(Lump {
+                name,
+                size: size as usize,
+                source: LumpSource::File {
+                    file: wad.clone(),
+                    offset: offset as u64,
+                },
+                data: OnceCell::new(),
+            })
+        })
+        .collect()
+}
+
+/// Groups the directory indices of every lump by name
+fn index_names(lumps: &[Lump]) -> HashMap<String, Vec<usize>> {
+    lumps
+        .iter()
+        .enumerate()
+        .fold(HashMap::new(), |mut result, (index, lump)| {
             result
-        });
+                .entry(lump.name.clone())
+                .or_insert_with(Vec::new)
+                .push(index);
+            result
+        })
+}
+
+/// Finds the lumps between the first start marker and the end marker after it
+fn find_namespace(lumps: &[Lump], start: &str, end: &str) -> Option<Range<usize>> {
+    let first = lumps.iter().position(|lump| lump.name == start)? + 1;
+    let last = lumps[first..].iter().position(|lump| lump.name == end)? + first;
+    Some(first..last)
+}
+
+/// The marker delimited lump groups that PWADs are merged into instead of appended to
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Namespace {
+    Sprites,
+    Flats,
+}
+
+impl Namespace {
+    /// The start and end marker used by the IWAD for this namespace
+    fn markers(&self) -> (&'static str, &'static str) {
+        match self {
+            Namespace::Sprites => (SPRITES_START, SPRITES_END),
+            Namespace::Flats => (FLATS_START, FLATS_END),
+        }
+    }
+}
+
+/// What a namespace marker lump does while merging a PWAD
+enum NamespaceMarker {
+    Start(Namespace),
+    End,
+    /// Markers like `F1_START` that only group lumps inside a namespace
+    Nested,
+}
+
+/// Returns true for the markers like `F1_START` nested inside a namespace
+fn is_nested_marker(lump: &Lump) -> bool {
+    matches!(namespace_marker(&lump.name), Some(NamespaceMarker::Nested))
+}
+
+fn namespace_marker(name: &str) -> Option<NamespaceMarker> {
+    match name {
+        "S_START" | "SS_START" => Some(NamespaceMarker::Start(Namespace::Sprites)),
+        "F_START" | "FF_START" => Some(NamespaceMarker::Start(Namespace::Flats)),
+        "S_END" | "SS_END" | "F_END" | "FF_END" => Some(NamespaceMarker::End),
+        "F1_START" | "F2_START" | "F3_START" | "F1_END" | "F2_END" | "F3_END" => {
+            Some(NamespaceMarker::Nested)
+        }
+        _ => None,
+    }
+}
+
+impl WadFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WadError> {
+        Ok(Self::from_lumps(read_lumps(&resolve_wad_path(
+            path.as_ref(),
+        ))?))
+    }
+
+    /// Loads an IWAD and layers any number of PWADs on top of it
+    /// # Arguments
+    /// * `iwad` - The path of the IWAD
+    /// * `pwads` - The paths of the PWADs, later files override earlier ones
+    /// # Examples
+    /// ```no_run
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load_with_pwads("doom1.wad", &["mymap.wad"]).unwrap();
+    /// ```
+    pub fn load_with_pwads(
+        iwad: impl AsRef<Path>,
+        pwads: &[impl AsRef<Path>],
+    ) -> Result<Self, WadError> {
+        let mut wad = Self::load(iwad)?;
+        for pwad in pwads {
+            wad.add_file(pwad.as_ref())?;
+        }
+        Ok(wad)
+    }
+
+    /// Layers a PWAD on top of the loaded lumps
+    /// # Remarks
+    /// Like vanilla's `-file`, the lumps are appended to the directory, so lookups by name find
+    /// the PWAD lump first and a PWAD map marker replaces the whole map block. Sprites and flats
+    /// are merged into the existing S_START/S_END and F_START/F_END namespaces instead, replacing
+    /// lumps with the same name and adding new ones, so the namespaces stay contiguous.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> Result<(), WadError> {
+        let lumps = read_lumps(&resolve_wad_path(path.as_ref()))?;
+        self.merge_lumps(lumps);
+        Ok(())
+    }
+
+    /// Merges lumps on top of the loaded lumps, see `add_file`
+    /// # Remarks
+    /// The namespace lumps are set aside and merged at the end, so the name index is only
+    /// rebuilt once however many lumps the PWAD holds.
+    pub fn merge_lumps(&mut self, lumps: Vec<Lump>) {
+        let mut namespaces = [
+            (Namespace::Sprites, Vec::new()),
+            (Namespace::Flats, Vec::new()),
+        ];
+        let mut namespace = None;
+        for lump in lumps {
+            match (namespace_marker(&lump.name), namespace) {
+                (Some(NamespaceMarker::Start(start)), _) => namespace = Some(start),
+                (Some(NamespaceMarker::End), _) => namespace = None,
+                (Some(NamespaceMarker::Nested), Some(_)) => {}
+                (_, Some(current)) => {
+                    if let Some((_, lumps)) = namespaces.iter_mut().find(|(n, _)| *n == current) {
+                        lumps.push(lump);
+                    }
+                }
+                (_, None) => self.lumps.push(lump),
+            }
+        }
+        for (namespace, lumps) in namespaces {
+            if !lumps.is_empty() {
+                self.merge_into_namespace(namespace, lumps);
+            }
+        }
+        self.names = index_names(&self.lumps);
+        self.clear_cache();
+    }
+
+    /// Throws away all decoded resources
+    /// # Remarks
+    /// This happens automatically when PWADs are merged; call it after changing `lumps` directly.
+    pub fn clear_cache(&mut self) {
+        self.cache = ResourceCache::default();
+    }
 
+    /// Replaces the lumps with the same name in a namespace, and adds the others at its end
+    /// # Remarks
+    /// The namespace is looked up without the name index, which is out of date while merging.
+    fn merge_into_namespace(&mut self, namespace: Namespace, lumps: Vec<Lump>) {
+        let (start, end) = namespace.markers();
+        let range = match find_namespace(&self.lumps, start, end) {
+            Some(range) => range,
+            None => {
+                // Create the namespace if there is none yet
+                self.lumps.push(Lump::marker(start));
+                self.lumps.push(Lump::marker(end));
+                self.lumps.len() - 1..self.lumps.len() - 1
+            }
+        };
+
+        // Where each name ends up; the added lumps are inserted before the end marker. The
+        // last lump with a name is replaced, since that is the one `get_lump` finds.
+        let mut positions: HashMap<String, usize> = self.lumps[range.clone()]
+            .iter()
+            .enumerate()
+            .map(|(index, lump)| (lump.name.clone(), range.start + index))
+            .collect();
+        let mut added = Vec::new();
+        for lump in lumps {
+            match positions.get(&lump.name) {
+                Some(&index) if index < range.end => self.lumps[index] = lump,
+                Some(&index) => added[index - range.end] = lump,
+                None => {
+                    positions.insert(lump.name.clone(), range.end + added.len());
+                    added.push(lump);
+                }
+            }
+        }
+        self.lumps.splice(range.end..range.end, added);
+    }
+
+    /// Creates a WAD file from a list of lumps in directory order
+    pub fn from_lumps(lumps: Vec<Lump>) -> Self {
+        let names = index_names(&lumps);
         Self {
-            lumps: lumps.clone(),
+            lumps,
+            names,
+            cache: ResourceCache::default(),
+        }
+    }
+
+    /// Gets the data of a lump by name
+    /// # Remarks
+    /// When several lumps share a name, the last one in the directory wins, just like vanilla's
+    /// `W_CheckNumForName`. A lump that can't be read from its file is treated as missing; use
+    /// `require_lump` to get the error instead.
+    pub fn get_lump(&self, name: &str) -> Option<&[u8]> {
+        self.get_lump_index(name)
+            .and_then(|index| self.lumps[index].data().ok())
+    }
+
+    /// Gets the directory index of the last lump with the given name
+    pub fn get_lump_index(&self, name: &str) -> Option<usize> {
+        self.get_lump_indices(name).last().copied()
+    }
+
+    /// Gets the directory indices of every lump with the given name, in directory order
+    pub fn get_lump_indices(&self, name: &str) -> &[usize] {
+        self.names
+            .get(&name.to_ascii_uppercase())
+            .map(|indices| indices.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Gets the lump at the given directory index
+    pub fn get_lump_by_index(&self, index: usize) -> Option<&Lump> {
+        self.lumps.get(index)
+    }
+
+    /// Gets the lump `offset` entries after the last lump named `marker`
+    /// # Examples
+    /// ```no_run
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// // The lump directly after a map marker holds the map's things
+    /// let things = wad.get_lump_after("E1M3", 1).unwrap();
+    /// assert_eq!(things.name, "THINGS");
+    /// ```
+    pub fn get_lump_after(&self, marker: &str, offset: usize) -> Option<&Lump> {
+        self.get_lump_index(marker)
+            .and_then(|index| self.lumps.get(index + offset))
+    }
+
+    /// Gets a lump from the map block following the map marker `map`
+    /// # Arguments
+    /// * `map` - The map marker, e.g. `E1M1` or `MAP01`
+    /// * `name` - The name of the map lump, e.g. `LINEDEFS`
+    pub fn get_map_lump(&self, map: &str, name: &str) -> Option<&Lump> {
+        let offset = MAP_LUMP_NAMES.iter().position(|n| *n == name)? + 1;
+        self.get_lump_after(map, offset)
+            .filter(|lump| lump.name == name)
+    }
+
+    /// Gets the directory indices of the lumps between a start and an end marker
+    /// # Remarks
+    /// The markers themselves are excluded, nested markers (like `F1_START`) are not.
+    pub fn get_namespace_range(&self, start: &str, end: &str) -> Option<Range<usize>> {
+        let first = self.get_lump_indices(start).first()? + 1;
+        let last = self.lumps[first..]
+            .iter()
+            .position(|lump| lump.name == end)?
+            + first;
+        Some(first..last)
+    }
+
+    /// Gets all lumps between a start and an end marker, e.g. `S_START` and `S_END`
+    pub fn get_namespace(&self, start: &str, end: &str) -> &[Lump] {
+        self.get_namespace_range(start, end)
+            .map(|range| &self.lumps[range])
+            .unwrap_or_default()
+    }
+
+    /// Gets the data of a lump by name, or a `MissingLump` error if there is no such lump
+    pub fn require_lump(&self, name: &str) -> Result<&[u8], WadError> {
+        self.lumps[self.require_lump_index(name)?].data()
+    }
+
+    /// Gets the index of the last lump with a name, or a `MissingLump` error
+    fn require_lump_index(&self, name: &str) -> Result<usize, WadError> {
+        self.get_lump_index(name)
+            .ok_or_else(|| WadError::MissingLump {
+                name: name.to_string(),
+            })
+    }
+
+    /// Gets the first palette of PLAYPAL
+    pub fn get_palette(&self) -> Result<Palette, WadError> {
+        Ok(self.get_palettes()?[0].clone())
+    }
+
+    /// Gets all palettes of PLAYPAL, normally 14 of them
+    pub fn get_palettes(&self) -> Result<Rc<[Palette]>, WadError> {
+        let index = self.require_lump_index(PALETTE_LUMP_NAME)?;
+        self.cache.palettes.get_or_decode(index, || {
+            let data = self.lumps[index].data()?;
+            if data.len() < PALETTE_SIZE {
+                return Err(WadError::TruncatedLump {
+                    name: PALETTE_LUMP_NAME.to_string(),
+                    offset: data.len(),
+                });
+            }
+            data.chunks_exact(PALETTE_SIZE)
+                .map(|palette| decode_palette(PALETTE_LUMP_NAME, palette))
+                .collect()
+        })
+    }
+
+    /// Decodes the tables of COLORMAP
+    pub fn get_colormaps(&self) -> Result<Vec<Colormap>, WadError> {
+        decode_colormaps(COLORMAP_LUMP_NAME, self.require_lump(COLORMAP_LUMP_NAME)?)
+    }
+
+    /// Gets a picture lump decoded to its columns and posts
+    pub fn get_patch(&self, name: &str) -> Result<Rc<Patch>, WadError> {
+        let index = self.require_lump_index(name)?;
+        self.cache.patches.get_or_decode(index, || {
+            decode_patch(name, self.lumps[index].data()?).map(Rc::new)
+        })
+    }
+
+    /// Gets a picture lump decoded to palette indices
+    pub fn get_image(&self, name: &str) -> Result<Rc<Picture>, WadError> {
+        let index = self.require_lump_index(name)?;
+        self.cache.pictures.get_or_decode(index, || {
+            decode_lump_image(name, self.lumps[index].data()?).map(Rc::new)
+        })
+    }
+
+    /// Gets a wall texture, compositing it on first use
+    /// # Remarks
+    /// PNAMES, TEXTURE1 and TEXTURE2 are parsed the first time any texture is asked for.
+    /// # Examples
+    /// ```no_run
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let texture = wad.get_texture("STARTAN3").unwrap();
+    /// let column = texture.column(17);
+    /// ```
+    pub fn get_texture(&self, name: &str) -> Result<Rc<Texture>, WadError> {
+        let mut textures = self.cache.textures.borrow_mut();
+        if textures.is_none() {
+            *textures = Some(TextureSet::load(self)?);
         }
+        textures
+            .as_mut()
+            .expect("Textures were just loaded")
+            .get(self, name)
     }
 
-    pub fn get_lump(&self, name: &str) -> Option<&Vec<u8>> {
-        self.lumps.get(name)
+    /// Gets a digitized sound lump, e.g. `DSPISTOL`
+    pub fn get_sound(&self, name: &str) -> Result<Arc<Sound>, WadError> {
+        let index = self.require_lump_index(name)?;
+        self.cache.sounds.get_or_decode(index, || {
+            Sound::decode(name, self.lumps[index].data()?).map(Arc::new)
+        })
     }
 
-    pub fn get_image(&self, name: &str) -> Result<DynamicImage, std::io::Error> {
-        let lump = self
-            .get_lump(name)
-            .expect(format!("Image with name {} not found", name).as_str());
-        let palette = self.get_lump(PALETTE_LUMP_NAME).expect("Palette not found");
-        let palette = decode_palette(palette);
-        decode_lump_image(lump, palette)
+    /// Gets a PC speaker sound lump, e.g. `DPPISTOL`, synthesized to a square wave
+    pub fn get_pc_sound(&self, name: &str) -> Result<Arc<Sound>, WadError> {
+        let index = self.require_lump_index(name)?;
+        self.cache.sounds.get_or_decode(index, || {
+            let sound = PcSound::decode(name, self.lumps[index].data()?)?;
+            Ok(Arc::new(sound.to_sound()))
+        })
     }
+
+    /// Gets a sound effect in the version of a sound device
+    /// # Arguments
+    /// * `name` - The name of the sound effect without a prefix, e.g. `PISTOL`
+    /// * `device` - Whether to get the digitized or the PC speaker version
+    pub fn get_sfx(&self, name: &str, device: SfxDevice) -> Result<Arc<Sound>, WadError> {
+        let lump_name = device.lump_name(name);
+        match device {
+            SfxDevice::Digital => self.get_sound(&lump_name),
+            SfxDevice::PcSpeaker => self.get_pc_sound(&lump_name),
+        }
+    }
+
+    /// Gets a MUS music lump, e.g. `D_E1M1`
+    pub fn get_music(&self, name: &str) -> Result<Arc<Mus>, WadError> {
+        let index = self.require_lump_index(name)?;
+        self.cache.music.get_or_decode(index, || {
+            Mus::decode(name, self.lumps[index].data()?).map(Arc::new)
+        })
+    }
+
+    /// Gets the OPL instrument bank of the GENMIDI lump, used to play music
+    pub fn get_genmidi(&self) -> Result<Arc<GenMidi>, WadError> {
+        let index = self.require_lump_index(GENMIDI_LUMP_NAME)?;
+        self.cache.genmidi.get_or_decode(index, || {
+            GenMidi::decode(GENMIDI_LUMP_NAME, self.lumps[index].data()?).map(Arc::new)
+        })
+    }
+
+    /// Gets the names of all flats, in directory order
+    /// # Remarks
+    /// The nested `F1_START`/`F1_END` style markers are skipped.
+    pub fn flat_names(&self) -> Vec<&str> {
+        self.get_namespace(FLATS_START, FLATS_END)
+            .iter()
+            .filter(|lump| !is_nested_marker(lump))
+            .map(|lump| lump.name.as_str())
+            .collect()
+    }
+
+    /// Decodes a flat from the F_START/F_END namespace
+    /// # Examples
+    /// ```no_run
+    /// use doom::wad::WadFile;
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let flat = wad.get_flat("FLOOR4_8").unwrap();
+    /// let image = flat.to_image(&wad.get_palette().unwrap());
+    /// ```
+    pub fn get_flat(&self, name: &str) -> Result<Rc<Flat>, WadError> {
+        let name = name.to_ascii_uppercase();
+        let index = self
+            .get_namespace_range(FLATS_START, FLATS_END)
+            .and_then(|range| {
+                range.rev().find(|&index| {
+                    let lump = &self.lumps[index];
+                    lump.name == name && !is_nested_marker(lump)
+                })
+            })
+            .ok_or_else(|| WadError::MissingLump { name: name.clone() })?;
+        self.cache.flats.get_or_decode(index, || {
+            Flat::decode(&name, self.lumps[index].data()?).map(Rc::new)
+        })
+    }
+}
+
+/// The kind of WAD file to write
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum WadKind {
+    /// A complete game, like doom.wad
+    Iwad,
+    /// A patch that is loaded on top of an IWAD, like a map or a graphics replacement
+    Pwad,
+}
+
+/// Assembles lumps into a WAD file
+/// # Remarks
+/// Lumps are written in the order they are added. The directory comes last, after the lump data,
+/// like the WAD files written by the id tools.
+/// # Examples
+/// ```
+/// use doom::wad::{WadKind, WadWriter};
+/// # let (things, linedefs, imp) = (vec![0; 10], vec![0; 14], vec![0; 8]);
+/// let mut writer = WadWriter::new(WadKind::Pwad);
+/// writer
+///     .add_map("MAP01", vec![("THINGS", things), ("LINEDEFS", linedefs)])?
+///     .add_namespace("SS_START", "SS_END", vec![("TROOA1", imp)])?;
+/// let data = writer.to_bytes()?; // Or writer.write("mymap.wad")?
+/// assert_eq!(&data[..4], b"PWAD");
+/// # Ok::<(), doom::wad::WadError>(())
+/// ```
+pub struct WadWriter {
+    kind: WadKind,
+    lumps: Vec<Lump>,
+}
+
+impl WadWriter {
+    pub fn new(kind: WadKind) -> Self {
+        Self {
+            kind,
+            lumps: Vec::new(),
+        }
+    }
+
+    /// Adds a lump
+    /// # Returns
+    /// The writer, or a `BadLumpName` error if the name doesn't fit in the directory
+    pub fn add_lump(&mut self, name: &str, data: Vec<u8>) -> Result<&mut Self, WadError> {
+        let name = encode_name(name)?;
+        self.lumps.push(Lump::new(&decode_name(&name), data));
+        Ok(self)
+    }
+
+    /// Adds an empty marker lump, like `F_START`
+    pub fn add_marker(&mut self, name: &str) -> Result<&mut Self, WadError> {
+        self.add_lump(name, Vec::new())
+    }
+
+    /// Adds a map marker followed by the lumps of the map
+    /// # Arguments
+    /// * `map` - The map marker, e.g. `E1M1` or `MAP01`
+    /// * `lumps` - The map lumps by name, e.g. `THINGS`, in any order
+    /// # Remarks
+    /// The lumps are written in the order of `MAP_LUMP_NAMES`, which is the order the game
+    /// expects them in. Lumps that aren't given are written empty; lump names that aren't map
+    /// lumps are rejected with a `BadLumpName` error.
+    pub fn add_map(
+        &mut self,
+        map: &str,
+        lumps: Vec<(&str, Vec<u8>)>,
+    ) -> Result<&mut Self, WadError> {
+        let mut lumps = lumps
+            .into_iter()
+            .map(|(name, data)| {
+                let name = name.to_ascii_uppercase();
+                match MAP_LUMP_NAMES.contains(&name.as_str()) {
+                    true => Ok((name, data)),
+                    false => Err(WadError::BadLumpName { name }),
+                }
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        self.add_marker(map)?;
+        for name in MAP_LUMP_NAMES {
+            self.add_lump(name, lumps.remove(name).unwrap_or_default())?;
+        }
+        Ok(self)
+    }
+
+    /// Adds lumps between a start and an end marker, e.g. `FF_START` and `FF_END` for flats
+    pub fn add_namespace(
+        &mut self,
+        start: &str,
+        end: &str,
+        lumps: Vec<(&str, Vec<u8>)>,
+    ) -> Result<&mut Self, WadError> {
+        self.add_marker(start)?;
+        for (name, data) in lumps {
+            self.add_lump(name, data)?;
+        }
+        self.add_marker(end)
+    }
+
+    /// Builds the WAD file
+    /// # Returns
+    /// The WAD file, or an `Unencodable` error if it is too large for the 32 bit offsets and
+    /// sizes of the directory
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        let mut data = Vec::new();
+        let mut directory = Vec::with_capacity(self.lumps.len() * DIRECTORY_ENTRY_SIZE);
+        for lump in &self.lumps {
+            let offset = encode_i32(&lump.name, WAD_HEADER_SIZE + data.len())?;
+            let size = encode_i32(&lump.name, lump.size())?;
+            data.extend_from_slice(lump.data()?);
+
+            let mut entry = [0; DIRECTORY_ENTRY_SIZE];
+            LittleEndian::write_i32(&mut entry[0..4], offset);
+            LittleEndian::write_i32(&mut entry[4..8], size);
+            entry[8..16].copy_from_slice(&encode_name(&lump.name)?);
+            directory.extend_from_slice(&entry);
+        }
+
+        let mut header = [0; WAD_HEADER_SIZE];
+        header[..4].copy_from_slice(match self.kind {
+            WadKind::Iwad => b"IWAD",
+            WadKind::Pwad => b"PWAD",
+        });
+        LittleEndian::write_i32(
+            &mut header[4..8],
+            encode_i32(DIRECTORY_NAME, self.lumps.len())?,
+        );
+        LittleEndian::write_i32(
+            &mut header[8..12],
+            encode_i32(DIRECTORY_NAME, WAD_HEADER_SIZE + data.len())?,
+        );
+
+        Ok([&header[..], &data, &directory].concat())
+    }
+
+    /// Writes the WAD file
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), WadError> {
+        let path = path.as_ref();
+        let data = self.to_bytes()?;
+        File::create(path)
+            .and_then(|mut file| file.write_all(&data))
+            .map_err(|source| WadError::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
+
+/// Converts an offset, size or count to the signed 32 bit value a WAD file stores
+/// # Arguments
+/// * `name` - The name of the lump the value belongs to, used to report errors
+/// * `value` - The value to convert
+fn encode_i32(name: &str, value: usize) -> Result<i32, WadError> {
+    i32::try_from(value).map_err(|_| WadError::Unencodable {
+        name: name.to_string(),
+        reason: format!("{} doesn't fit in the 32 bits of a WAD file", value),
+    })
+}
+
+/// Encodes a lump name for the directory, padded with zeroes to 8 bytes
+fn encode_name(name: &str) -> Result<[u8; 8], WadError> {
+    if name.is_empty() || name.len() > 8 || !name.is_ascii() || name.contains('\0') {
+        return Err(WadError::BadLumpName {
+            name: name.to_string(),
+        });
+    }
+    let mut encoded = [0; 8];
+    encoded[..name.len()].copy_from_slice(name.to_ascii_uppercase().as_bytes());
+    Ok(encoded)
+}
+
+/// Decodes a lump or texture name, which is padded with zeroes to 8 bytes
+pub fn decode_name(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| (byte as char).to_ascii_uppercase())
+        .collect()
 }
 
-#[allow(dead_code)]
 struct PictureHeader {
     width: i16,
     height: i16,
@@ -59,112 +1036,591 @@ struct PictureHeader {
     top_offset: i16,
 }
 
-struct Post {
-    top_delta: u8,
-    length: u8,
-    data: Vec<u8>,
+/// A picture in Doom's column format, decoded to its posts
+/// # Remarks
+/// This is the format of patches, sprites and most other graphics. Each column is a list of posts,
+/// runs of opaque pixels; everything between the posts is transparent.
+pub struct Patch {
+    pub width: usize,
+    pub height: usize,
+    pub left_offset: i16,
+    pub top_offset: i16,
+    pub columns: Vec<Column>,
+}
+
+/// A column of a patch
+#[derive(Debug, PartialEq)]
+pub struct Column {
+    pub posts: Vec<Post>,
 }
 
-struct Column {
-    posts: Vec<Post>,
+/// A run of opaque pixels in a column
+#[derive(Debug, PartialEq)]
+pub struct Post {
+    /// The row of the first pixel
+    pub top: usize,
+    /// The palette indices of the pixels
+    pub data: Vec<u8>,
 }
 
-fn decode_picture_header(cursor: &mut Cursor<Vec<u8>>) -> PictureHeader {
-    let width = cursor.read_i16::<LittleEndian>().unwrap();
-    let height = cursor.read_i16::<LittleEndian>().unwrap();
-    let left_offset = cursor.read_i16::<LittleEndian>().unwrap();
-    let top_offset = cursor.read_i16::<LittleEndian>().unwrap();
+fn decode_picture_header(reader: &mut LumpReader) -> Result<PictureHeader, WadError> {
+    let width = reader.read_i16()?;
+    let height = reader.read_i16()?;
+    let left_offset = reader.read_i16()?;
+    let top_offset = reader.read_i16()?;
+
+    if width <= 0 || height <= 0 {
+        return Err(WadError::BadHeader {
+            name: reader.name().to_string(),
+            offset: 0,
+            reason: format!("invalid picture size {}x{}", width, height),
+        });
+    }
 
-    PictureHeader {
+    Ok(PictureHeader {
         width,
         height,
         left_offset,
         top_offset,
-    }
+    })
 }
 
-fn decode_post(cursor: &mut Cursor<Vec<u8>>) -> Result<Post, std::io::Error> {
-    let top_delta = cursor.read_u8()?;
+fn decode_post(reader: &mut LumpReader, top: usize) -> Result<Post, WadError> {
+    let length = reader.read_u8()?;
+    reader.read_u8()?; // Padding byte
 
-    // End of column's data
-    if top_delta == 0xFF {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::UnexpectedEof,
-            "End of column data",
-        ));
+    let data = reader.read_bytes(length as usize)?.to_vec();
+
+    reader.read_u8()?; // Padding byte
+
+    Ok(Post { top, data })
+}
+
+/// Decodes the posts of a column
+/// # Remarks
+/// Patches taller than 254 pixels can't express their lower posts with a single byte offset. The
+/// DeePsea convention is used for these: an offset that isn't below the previous post is relative
+/// to the previous post instead of the top of the column.
+fn decode_column(reader: &mut LumpReader) -> Result<Column, WadError> {
+    let mut posts: Vec<Post> = Vec::new();
+
+    loop {
+        let top_delta = reader.read_u8()?;
+        // End of column's data
+        if top_delta == 0xFF {
+            return Ok(Column { posts });
+        }
+        let top = match posts.last() {
+            Some(last) if top_delta as usize <= last.top => last.top + top_delta as usize,
+            _ => top_delta as usize,
+        };
+        posts.push(decode_post(reader, top)?);
     }
+}
 
-    let length = cursor.read_u8()?;
-    cursor.read_u8()?; // Padding byte
+/// Decodes a lump in Doom's picture format
+pub fn decode_patch(name: &str, data: &[u8]) -> Result<Patch, WadError> {
+    if data.starts_with(PNG_SIGNATURE) {
+        return Err(WadError::UnsupportedFormat {
+            name: name.to_string(),
+            offset: 0,
+            reason: "PNG graphics are not supported".to_string(),
+        });
+    }
 
-    let mut data = vec![0; length as usize];
-    cursor.read_exact(&mut data)?;
+    let mut reader = LumpReader::new(name, data);
+    let picture_header = decode_picture_header(&mut reader)?;
 
-    cursor.read_u8()?; // Padding byte
+    let column_offsets = (0..picture_header.width)
+        .map(|_| reader.read_u32())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Columns may share their posts or be stored in any order, so the only offsets that can't
+    // be right are the ones pointing into the header or past the end of the lump
+    let data_start = reader.position();
+    let mut columns = Vec::new();
+    for (column, &offset) in column_offsets.iter().enumerate() {
+        if offset as usize >= data.len() || (offset as usize) < data_start {
+            return Err(WadError::BadColumnOffset {
+                name: name.to_string(),
+                column,
+                offset: offset as usize,
+            });
+        }
+        reader.set_position(offset as usize);
+        columns.push(decode_column(&mut reader)?);
+    }
 
-    Ok(Post {
-        top_delta,
-        length,
-        data,
+    Ok(Patch {
+        width: picture_header.width as usize,
+        height: picture_header.height as usize,
+        left_offset: picture_header.left_offset,
+        top_offset: picture_header.top_offset,
+        columns,
     })
 }
 
-fn decode_column(cursor: &mut Cursor<Vec<u8>>) -> Result<Column, std::io::Error> {
-    let mut posts = Vec::new();
+/// A decoded picture along with its offsets
+/// # Remarks
+/// The offsets move the picture relative to the position it is drawn at: a picture drawn at
+/// (x, y) has its top left corner at (x - left_offset, y - top_offset). Sprites use this to
+/// stand on their origin and weapons and status bar pieces to line up on screen.
+#[derive(Debug, PartialEq)]
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub left_offset: i16,
+    pub top_offset: i16,
+    /// The palette indices, row by row; pixels not covered by a post are 0
+    pub pixels: Vec<u8>,
+    /// Whether a post covers the pixel, row by row
+    pub opaque: Vec<bool>,
+}
+
+impl Picture {
+    /// Gets the palette index at a position
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
 
-    while let Ok(post) = decode_post(cursor) {
-        posts.push(post);
+    /// Returns true if a post covers the pixel at a position
+    /// # Remarks
+    /// This is what makes a pixel transparent, not its color: palette index 0 is black and is
+    /// drawn like any other color when a post covers it.
+    pub fn is_opaque(&self, x: usize, y: usize) -> bool {
+        self.opaque[y * self.width + x]
     }
 
-    Ok(Column { posts })
+    /// Converts an image to palette indices
+    /// # Arguments
+    /// * `image` - The image to convert
+    /// * `palette` - The palette to map the colors to, usually the first palette of PLAYPAL
+    /// * `left_offset` - The left offset of the picture
+    /// * `top_offset` - The top offset of the picture
+    /// # Remarks
+    /// Every color is mapped to the nearest color of the palette. Pixels that are more than half
+    /// transparent become transparent, so images without an alpha channel are fully opaque.
+    /// # Examples
+    /// ```no_run
+    /// use doom::wad::{encode_picture, Picture, WadFile};
+    /// let wad = WadFile::load("doom1.wad").unwrap();
+    /// let image = image::open("imp.png").unwrap();
+    /// let picture = Picture::from_image(&image, &wad.get_palette().unwrap(), 20, 55);
+    /// let lump = encode_picture("TROOA1", &picture).unwrap();
+    /// ```
+    pub fn from_image(
+        image: &DynamicImage,
+        palette: &[[u8; 3]],
+        left_offset: i16,
+        top_offset: i16,
+    ) -> Self {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+        let (pixels, opaque) = image
+            .pixels()
+            .map(|(_, _, pixel)| {
+                let rgb = [pixel[0], pixel[1], pixel[2]];
+                let index = *colors
+                    .entry(rgb)
+                    .or_insert_with(|| nearest_color(rgb, palette));
+                (index, pixel[3] >= 128)
+            })
+            .unzip();
+
+        Self {
+            width,
+            height,
+            left_offset,
+            top_offset,
+            pixels,
+            opaque,
+        }
+    }
+
+    /// Converts the picture to an RGBA image using the given palette
+    /// # Remarks
+    /// Pixels that no post covers are fully transparent.
+    pub fn to_image(&self, palette: &[[u8; 3]]) -> DynamicImage {
+        indexed_to_image(
+            &self.pixels,
+            Some(&self.opaque),
+            self.width,
+            self.height,
+            palette,
+        )
+    }
 }
 
-fn decode_lump_image(
-    data: &Vec<u8>,
-    palette: Vec<[u8; 3]>,
-) -> Result<DynamicImage, std::io::Error> {
-    let mut cursor = Cursor::new(data.clone());
-    let picture_header = decode_picture_header(&mut cursor);
+/// Encodes a picture in Doom's picture format
+/// # Arguments
+/// * `name` - The name of the lump, used to report errors
+/// * `picture` - The picture to encode
+/// # Remarks
+/// Every run of opaque pixels in a column becomes a post. Posts that start below row 254 use the
+/// DeePsea convention that `decode_patch` understands: their offset is relative to the previous
+/// post, and empty posts are added where the offset can't be expressed otherwise.
+pub fn encode_picture(name: &str, picture: &Picture) -> Result<Vec<u8>, WadError> {
+    let (width, height) = (picture.width, picture.height);
+    if width == 0 || height == 0 || width > i16::MAX as usize || height > i16::MAX as usize {
+        return Err(WadError::Unencodable {
+            name: name.to_string(),
+            reason: format!("a picture can't be {}x{}", width, height),
+        });
+    }
 
-    let column_offsets: Vec<u32> = (0..picture_header.width)
-        .map(|_| cursor.read_u32::<LittleEndian>().unwrap())
-        .collect();
+    let mut header = Vec::with_capacity(8 + width * 4);
+    header.extend_from_slice(&(width as i16).to_le_bytes());
+    header.extend_from_slice(&(height as i16).to_le_bytes());
+    header.extend_from_slice(&picture.left_offset.to_le_bytes());
+    header.extend_from_slice(&picture.top_offset.to_le_bytes());
 
     let mut columns = Vec::new();
-    for &offset in &column_offsets {
-        cursor.set_position(offset as u64);
-        columns.push(decode_column(&mut cursor)?);
+    for x in 0..width {
+        let offset = 8 + width * 4 + columns.len();
+        header.extend_from_slice(&(offset as u32).to_le_bytes());
+        encode_column(&mut columns, picture, x);
+    }
+
+    Ok([header, columns].concat())
+}
+
+/// The largest top offset a post can have, 255 ends the column
+const MAX_POST_TOP: usize = 254;
+/// The most pixels a single post can hold
+const MAX_POST_LENGTH: usize = 255;
+
+fn encode_column(data: &mut Vec<u8>, picture: &Picture, x: usize) {
+    let mut last_top: Option<usize> = None;
+    let mut y = 0;
+    while y < picture.height {
+        if !picture.is_opaque(x, y) {
+            y += 1;
+            continue;
+        }
+        let length = (y..picture.height)
+            .take(MAX_POST_LENGTH)
+            .take_while(|&row| picture.is_opaque(x, row))
+            .count();
+
+        // Add empty posts until the top of this post can be expressed
+        loop {
+            // Absolute offsets must be below the previous post, or they are read as relative
+            let top_delta = match last_top {
+                _ if y <= MAX_POST_TOP && last_top.is_none_or(|last| y > last) => Some(y),
+                Some(last) if y - last <= last.min(MAX_POST_TOP) => Some(y - last),
+                _ => None,
+            };
+            if let Some(top_delta) = top_delta {
+                data.extend_from_slice(&[top_delta as u8, length as u8, 0]);
+                data.extend((y..y + length).map(|row| picture.pixel(x, row)));
+                data.push(0);
+                last_top = Some(y);
+                break;
+            }
+
+            let (top_delta, top) = match last_top {
+                Some(last) if last >= MAX_POST_TOP => {
+                    let step = last.min(MAX_POST_TOP);
+                    (step, last + step)
+                }
+                _ => (MAX_POST_TOP, MAX_POST_TOP),
+            };
+            data.extend_from_slice(&[top_delta as u8, 0, 0, 0]);
+            last_top = Some(top);
+        }
+        y += length;
     }
+    data.push(0xFF);
+}
+
+fn decode_lump_image(name: &str, data: &[u8]) -> Result<Picture, WadError> {
+    let patch = decode_patch(name, data)?;
+    let mut pixels = vec![0; patch.width * patch.height];
+    let mut opaque = vec![false; patch.width * patch.height];
 
     // Iterate over the columns and the posts within each column
-    let img = columns
+    patch
+        .columns
         .iter()
         .enumerate()
         .flat_map(|(x, column)| {
             column.posts.iter().flat_map(move |post| {
-                (0..post.length).map(move |y| (x, post.top_delta + y, post.data[y as usize]))
+                post.data
+                    .iter()
+                    .enumerate()
+                    .map(move |(y, &color_index)| (x, post.top + y, color_index))
             })
         })
-        .map(|(x, y, color_index)| (x, y, palette[color_index as usize]))
-        .fold(
-            // Create an image buffer with the same dimensions as the Doom image
-            ImageBuffer::new(picture_header.width as u32, picture_header.height as u32),
-            |mut img, (x, y, rgb)| {
-                img.put_pixel(x as u32, y as u32, Rgb(rgb));
-                img
-            },
-        );
+        // Posts reaching below the picture are clipped, like vanilla does
+        .filter(|(_, y, _)| *y < patch.height)
+        .for_each(|(x, y, color_index)| {
+            pixels[y * patch.width + x] = color_index;
+            opaque[y * patch.width + x] = true;
+        });
 
-    // Convert the image buffer to a DynamicImage and return it
-    Ok(DynamicImage::ImageRgb8(img))
+    Ok(Picture {
+        width: patch.width,
+        height: patch.height,
+        left_offset: patch.left_offset,
+        top_offset: patch.top_offset,
+        pixels,
+        opaque,
+    })
 }
 
-fn decode_palette(data: &Vec<u8>) -> Vec<[u8; 3]> {
-    (0..256).fold(Vec::new(), |mut result, i| {
-        let r = data[i * 3];
-        let g = data[i * 3 + 1];
-        let b = data[i * 3 + 2];
-        result.push([r, g, b]);
-        result
-    })
+fn decode_palette(name: &str, data: &[u8]) -> Result<Palette, WadError> {
+    let mut reader = LumpReader::new(name, data);
+    (0..256)
+        .map(|_| {
+            let rgb = reader.read_bytes(3)?;
+            Ok([rgb[0], rgb[1], rgb[2]])
+        })
+        .collect()
+}
+
+/// Decodes the colormap tables
+/// # Remarks
+/// Some PWADs ship a COLORMAP without the trailing black table, so only the 33 tables that are
+/// used are required.
+fn decode_colormaps(name: &str, data: &[u8]) -> Result<Vec<Colormap>, WadError> {
+    let mut reader = LumpReader::new(name, data);
+    let count = (data.len() / 256).min(NUM_COLORMAP_TABLES);
+    if count < NUM_COLORMAP_TABLES - 1 {
+        return Err(WadError::TruncatedLump {
+            name: name.to_string(),
+            offset: data.len(),
+        });
+    }
+    (0..count)
+        .map(|_| {
+            let mut colormap = [0; 256];
+            colormap.copy_from_slice(reader.read_bytes(256)?);
+            Ok(colormap)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a picture lump from its header values and raw column data
+    fn patch_lump(width: i16, height: i16, offsets: &[u32], columns: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [width, height, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for offset in offsets {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend_from_slice(columns);
+        data
+    }
+
+    #[test]
+    fn decode_patch_allows_shared_columns() {
+        // Both columns point at the same post: 2 pixels from row 1
+        let data = patch_lump(2, 4, &[16, 16], &[1, 2, 0, 7, 8, 0, 0xFF]);
+        let patch = decode_patch("SHARED", &data).unwrap();
+        assert_eq!(patch.columns.len(), 2);
+        assert_eq!(patch.columns[0], patch.columns[1]);
+        assert_eq!(
+            patch.columns[0].posts,
+            vec![Post {
+                top: 1,
+                data: vec![7, 8]
+            }]
+        );
+    }
+
+    #[test]
+    fn decode_patch_allows_columns_out_of_order() {
+        let columns = [0, 1, 0, 5, 0, 0xFF, 2, 1, 0, 6, 0, 0xFF];
+        let data = patch_lump(2, 4, &[22, 16], &columns);
+        let patch = decode_patch("REVERSED", &data).unwrap();
+        assert_eq!(patch.columns[0].posts[0].data, vec![6]);
+        assert_eq!(patch.columns[1].posts[0].data, vec![5]);
+    }
+
+    #[test]
+    fn decode_patch_rejects_offsets_into_the_header_or_past_the_end() {
+        let columns = [0, 1, 0, 5, 0, 0xFF];
+        for offset in [4, 18] {
+            let data = patch_lump(1, 4, &[offset], &columns);
+            assert!(matches!(
+                decode_patch("BAD", &data),
+                Err(WadError::BadColumnOffset { column: 0, .. })
+            ));
+        }
+    }
+
+    /// Builds a picture where the pixels are opaque where `color` returns a palette index
+    fn picture(width: usize, height: usize, color: impl Fn(usize, usize) -> Option<u8>) -> Picture {
+        let colors: Vec<_> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| color(x, y))
+            .collect();
+        Picture {
+            width,
+            height,
+            left_offset: -3,
+            top_offset: 12,
+            pixels: colors.iter().map(|color| color.unwrap_or(0)).collect(),
+            opaque: colors.iter().map(|color| color.is_some()).collect(),
+        }
+    }
+
+    #[test]
+    fn encode_picture_round_trips() {
+        // Holes in every column, with palette index 0 inside the posts
+        let picture = picture(5, 20, |x, y| match (x + y) % 4 {
+            0 => None,
+            _ => Some((x * 7 + y) as u8 % 3),
+        });
+        let data = encode_picture("ROUND", &picture).unwrap();
+        assert_eq!(decode_lump_image("ROUND", &data).unwrap(), picture);
+    }
+
+    #[test]
+    fn encode_picture_round_trips_tall_columns() {
+        // Posts below row 254 need DeePsea's relative top offsets, and a column taller than 255
+        // pixels needs several posts
+        let picture = picture(3, 600, |x, y| match x {
+            0 => Some(y as u8),
+            1 => (y >= 400 && y % 50 != 0).then_some(1),
+            _ => (y == 599).then_some(2),
+        });
+        let data = encode_picture("TALL", &picture).unwrap();
+        assert_eq!(decode_lump_image("TALL", &data).unwrap(), picture);
+    }
+
+    #[test]
+    fn wad_writer_round_trips() {
+        let mut writer = WadWriter::new(WadKind::Pwad);
+        writer
+            .add_lump("DEMO1", vec![1, 2, 3])
+            .unwrap()
+            .add_map(
+                "MAP01",
+                vec![("THINGS", vec![4; 10]), ("SECTORS", vec![5; 26])],
+            )
+            .unwrap()
+            .add_namespace("FF_START", "FF_END", vec![("floor7_2", vec![6; 4096])])
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("wad_writer_{}.wad", std::process::id()));
+        writer.write(&path).unwrap();
+        let wad = WadFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let wad = wad.unwrap();
+
+        assert_eq!(wad.lumps.len(), writer.lumps.len());
+        for (written, read) in writer.lumps.iter().zip(&wad.lumps) {
+            assert_eq!(read.name, written.name);
+            assert_eq!(read.data().unwrap(), written.data().unwrap());
+        }
+        assert_eq!(wad.get_lump("FLOOR7_2"), Some(&[6; 4096][..]));
+        assert_eq!(wad.get_lump("THINGS"), Some(&[4; 10][..]));
+        assert_eq!(wad.get_lump("LINEDEFS"), Some(&[][..]));
+    }
+
+    #[test]
+    fn offsets_and_sizes_must_fit_in_32_bits() {
+        assert_eq!(encode_i32("BIG", i32::MAX as usize).unwrap(), i32::MAX);
+        assert!(matches!(
+            encode_i32("BIG", i32::MAX as usize + 1),
+            Err(WadError::Unencodable { name, .. }) if name == "BIG"
+        ));
+    }
+
+    #[test]
+    fn wad_writer_rejects_long_names() {
+        let mut writer = WadWriter::new(WadKind::Pwad);
+        assert!(matches!(
+            writer.add_lump("TOOLONGNAME", Vec::new()),
+            Err(WadError::BadLumpName { .. })
+        ));
+    }
+
+    fn lumps(lumps: &[(&str, u8)]) -> Vec<Lump> {
+        lumps
+            .iter()
+            .map(|&(name, value)| match value {
+                0 => Lump::marker(name),
+                _ => Lump::new(name, vec![value]),
+            })
+            .collect()
+    }
+
+    fn names(wad: &WadFile) -> Vec<&str> {
+        wad.lumps.iter().map(|lump| lump.name.as_str()).collect()
+    }
+
+    #[test]
+    fn merged_namespace_lumps_replace_iwad_lumps() {
+        let mut wad = WadFile::from_lumps(lumps(&[
+            ("PLAYPAL", 1),
+            ("S_START", 0),
+            ("TROOA1", 1),
+            ("POSSA1", 1),
+            ("S_END", 0),
+            ("F_START", 0),
+            ("FLOOR0_1", 1),
+            ("F_END", 0),
+        ]));
+        wad.merge_lumps(lumps(&[
+            ("SS_START", 0),
+            ("TROOA1", 2),
+            ("NEWSA0", 2),
+            ("NEWSA0", 3),
+            ("SS_END", 0),
+            ("FF_START", 0),
+            ("F1_START", 0),
+            ("FLOOR0_1", 2),
+            ("F1_END", 0),
+            ("FF_END", 0),
+            ("MAP01", 2),
+        ]));
+
+        assert_eq!(
+            names(&wad),
+            vec![
+                "PLAYPAL", "S_START", "TROOA1", "POSSA1", "NEWSA0", "S_END", "F_START", "FLOOR0_1",
+                "F_END", "MAP01"
+            ]
+        );
+        assert_eq!(wad.get_lump("TROOA1"), Some(&[2][..]));
+        assert_eq!(wad.get_lump("NEWSA0"), Some(&[3][..]));
+        assert_eq!(wad.get_lump("FLOOR0_1"), Some(&[2][..]));
+        assert_eq!(wad.get_lump_indices("TROOA1"), &[2]);
+        assert_eq!(
+            wad.get_namespace_range(SPRITES_START, SPRITES_END),
+            Some(2..5)
+        );
+    }
+
+    #[test]
+    fn merged_lumps_replace_the_last_lump_with_their_name() {
+        let mut wad = WadFile::from_lumps(lumps(&[
+            ("S_START", 0),
+            ("TROOA1", 1),
+            ("TROOA1", 2),
+            ("S_END", 0),
+        ]));
+        wad.merge_lumps(lumps(&[("S_START", 0), ("TROOA1", 3), ("S_END", 0)]));
+
+        assert_eq!(names(&wad), vec!["S_START", "TROOA1", "TROOA1", "S_END"]);
+        assert_eq!(wad.lumps[1].data().unwrap(), &[1]);
+        assert_eq!(wad.get_lump("TROOA1"), Some(&[3][..]));
+    }
+
+    #[test]
+    fn merged_namespaces_are_created_when_missing() {
+        let mut wad = WadFile::from_lumps(lumps(&[("PLAYPAL", 1)]));
+        wad.merge_lumps(lumps(&[("S_START", 0), ("TROOA1", 2), ("S_END", 0)]));
+        assert_eq!(names(&wad), vec!["PLAYPAL", "S_START", "TROOA1", "S_END"]);
+        assert_eq!(
+            wad.get_namespace_range(SPRITES_START, SPRITES_END),
+            Some(2..3)
+        );
+    }
 }
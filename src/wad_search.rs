@@ -0,0 +1,222 @@
+use crate::wad::WadError;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The IWAD file names to look for when no IWAD is given, in order of preference
+pub const IWAD_NAMES: [&str; 8] = [
+    "doom2.wad",
+    "plutonia.wad",
+    "tnt.wad",
+    "doom.wad",
+    "doom1.wad",
+    "freedoom2.wad",
+    "freedoom1.wad",
+    "freedm.wad",
+];
+
+fn get_wad_dir() -> Option<PathBuf> {
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        // If the CARGO_MANIFEST_DIR environment variable is set, we're probably running with `cargo run`
+        // CARGO_MANIFEST_DIR points to the directory where your Cargo.toml exists.
+        Some(PathBuf::from(manifest_dir).join("wad"))
+    } else {
+        // Otherwise, we're probably running the program directly
+        // env::current_exe() gives us the path of the current executable
+        env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join("wad")))
+    }
+}
+
+/// Gets the directories that are searched for WAD files, in search order
+/// # Remarks
+/// These are the `wad` directory next to the executable, the working directory, `DOOMWADDIR`,
+/// every entry of `DOOMWADPATH` and the `games/doom` and `doom` directories of the XDG data
+/// directories, like other source ports do.
+pub fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    dirs.extend(get_wad_dir());
+    dirs.push(PathBuf::from("."));
+    if let Some(dir) = env::var_os("DOOMWADDIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(path) = env::var_os("DOOMWADPATH") {
+        dirs.extend(env::split_paths(&path));
+    }
+
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    let data_dirs = env::var_os("XDG_DATA_DIRS")
+        .map(|dirs| env::split_paths(&dirs).collect())
+        .unwrap_or_else(|| {
+            vec![
+                PathBuf::from("/usr/local/share"),
+                PathBuf::from("/usr/share"),
+            ]
+        });
+    for data_dir in data_home.into_iter().chain(data_dirs) {
+        dirs.push(data_dir.join("games/doom"));
+        dirs.push(data_dir.join("doom"));
+    }
+
+    // The same directory can show up more than once, e.g. when DOOMWADDIR is the working directory
+    dirs.iter()
+        .enumerate()
+        .fold(Vec::new(), |mut result, (i, dir)| {
+            if !dirs[..i].contains(dir) {
+                result.push(dir.clone());
+            }
+            result
+        })
+}
+
+/// Looks for a file in a directory, ignoring the case of the file name
+fn find_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    dir.read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| file_name.eq_ignore_ascii_case(name))
+        })
+}
+
+/// Finds a WAD file
+/// # Arguments
+/// * `name` - A path, or the file name of a WAD file in one of the search directories
+/// # Returns
+/// The path of the WAD file, or `None` if it can't be found
+pub fn find_wad_file(name: &Path) -> Option<PathBuf> {
+    if name.is_file() {
+        return Some(name.to_path_buf());
+    }
+    // Only bare file names are looked up in the search directories
+    let file_name = bare_file_name(name)?;
+
+    search_dirs()
+        .iter()
+        .find_map(|dir| find_in_dir(dir, file_name))
+}
+
+/// Finds the IWAD to play
+/// # Arguments
+/// * `name` - The IWAD given with `-iwad`, if any
+/// # Returns
+/// The path of the IWAD, or an `IwadNotFound` error listing every path that was tried
+/// # Examples
+/// ```no_run
+/// use doom::wad_search::find_iwad;
+/// // Finds doom2.wad, doom.wad, doom1.wad, ... in the search directories
+/// let iwad = find_iwad(None).unwrap();
+/// ```
+pub fn find_iwad(name: Option<&Path>) -> Result<PathBuf, WadError> {
+    if let Some(name) = name {
+        return find_wad_file(name).ok_or_else(|| WadError::IwadNotFound {
+            searched: match bare_file_name(name) {
+                Some(file_name) => search_dirs()
+                    .iter()
+                    .map(|dir| dir.join(file_name))
+                    .collect(),
+                None => vec![name.to_path_buf()],
+            },
+        });
+    }
+
+    find_iwad_in(&search_dirs())
+}
+
+/// Finds the first directory with an IWAD, preferring the IWADs in the order of `IWAD_NAMES`
+fn find_iwad_in(dirs: &[PathBuf]) -> Result<PathBuf, WadError> {
+    dirs.iter()
+        .find_map(|dir| IWAD_NAMES.iter().find_map(|name| find_in_dir(dir, name)))
+        .ok_or_else(|| WadError::IwadNotFound {
+            searched: dirs
+                .iter()
+                .flat_map(|dir| IWAD_NAMES.iter().map(move |name| dir.join(name)))
+                .collect(),
+        })
+}
+
+/// Gets the file name of a path that consists of just a file name
+fn bare_file_name(path: &Path) -> Option<&str> {
+    match path.components().count() {
+        1 => path.to_str(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Creates an empty temporary directory for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("wad_search_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn iwads_are_found_in_directory_order() {
+        let (first, second) = (temp_dir("first"), temp_dir("second"));
+        let dirs = [first.clone(), second.clone()];
+        assert!(matches!(
+            find_iwad_in(&dirs),
+            Err(WadError::IwadNotFound { searched }) if searched.len() == 2 * IWAD_NAMES.len()
+        ));
+
+        fs::write(second.join("doom2.wad"), []).unwrap();
+        fs::write(first.join("DOOM1.WAD"), []).unwrap();
+        // An earlier directory wins over a preferred IWAD in a later one
+        let found = find_iwad_in(&dirs);
+        fs::write(first.join("tnt.wad"), []).unwrap();
+        let preferred = find_iwad_in(&dirs);
+        fs::remove_dir_all(&first).unwrap();
+        fs::remove_dir_all(&second).unwrap();
+
+        assert_eq!(found.unwrap(), first.join("DOOM1.WAD"));
+        assert_eq!(preferred.unwrap(), first.join("tnt.wad"));
+    }
+
+    #[test]
+    fn doomwaddir_is_searched_before_doomwadpath() {
+        // This is the only test that changes the environment
+        let (wad_dir, path_dir) = (temp_dir("waddir"), temp_dir("wadpath"));
+        env::set_var("DOOMWADDIR", &wad_dir);
+        env::set_var(
+            "DOOMWADPATH",
+            env::join_paths([&path_dir, &wad_dir]).unwrap(),
+        );
+        let dirs = search_dirs();
+        let name = format!("search{}.wad", std::process::id());
+        fs::write(path_dir.join(name.to_ascii_uppercase()), []).unwrap();
+        let found = find_wad_file(Path::new(&name));
+        let missing = find_iwad(Some(Path::new("missing.wad")));
+        env::remove_var("DOOMWADDIR");
+        env::remove_var("DOOMWADPATH");
+        fs::remove_dir_all(&wad_dir).unwrap();
+        fs::remove_dir_all(&path_dir).unwrap();
+
+        let position = |dir: &Path| dirs.iter().position(|d| d == dir).unwrap();
+        assert!(position(Path::new(".")) < position(&wad_dir));
+        assert_eq!(position(&path_dir), position(&wad_dir) + 1);
+        assert_eq!(dirs.iter().filter(|&dir| dir == &wad_dir).count(), 1);
+        assert_eq!(found, Some(path_dir.join(name.to_ascii_uppercase())));
+        assert!(matches!(
+            missing,
+            Err(WadError::IwadNotFound { searched }) if searched.contains(&wad_dir.join("missing.wad"))
+        ));
+    }
+}